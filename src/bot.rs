@@ -1,4 +1,10 @@
 pub mod cmd;
 pub mod logic;
+mod card;
 mod change;
 pub mod clients;
+pub mod dialogue;
+pub mod i18n;
+pub mod jobs;
+pub mod notify;
+pub mod pace;
@@ -0,0 +1,176 @@
+use crate::bot::i18n::{Lang, Text};
+use crate::tencent::client::JOIN_QQ;
+use crate::tencent::model::ApplicationProgress;
+use crate::tencent::progress::Step;
+use reqwest::Url;
+use std::time::Duration;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+use teloxide::utils::html::escape;
+
+/// Steps every application goes through, in order. Assessments and written tests
+/// come up on the side and are shown on their own.
+const PIPELINE: [Step; 9] = [
+    Step::CvDeliverance,
+    Step::GroupInterview,
+    Step::PreliminaryInterview,
+    Step::SecondaryInterview,
+    Step::HrInterview,
+    Step::EmployerAssessment,
+    Step::EmployeeConfirmation,
+    Step::OfferConfirmation,
+    Step::Completed,
+];
+
+/// Renders the progress as an HTML message, with a button leading to where the
+/// application goes on: the assessment if one is waiting, or the recruiting site.
+/// `since_change` is how long ago the progress last changed, if known.
+pub fn status_card(
+    ap: &ApplicationProgress,
+    since_change: Option<Duration>,
+    lang: Lang,
+) -> (String, InlineKeyboardMarkup) {
+    let position = &ap.position_info;
+    let mut card = format!("<b>{}</b>\n", escape(&position.apply_position_txt));
+    let details = [
+        (Text::InterviewPosition, &position.interview_position_txt),
+        (Text::SubDirection, &position.sub_direction_id_txt),
+    ];
+    for (label, value) in details {
+        if let Some(value) = value.as_deref().filter(|v| !v.is_empty()) {
+            card += &format!("{}: {}\n", label.localize(lang), escape(value));
+        }
+    }
+    card += "\n";
+
+    match ap.get_current_step() {
+        Ok(Some(current)) => {
+            for step in PIPELINE {
+                let mark = if step < current || current == Step::Completed {
+                    "✅"
+                } else if step == current {
+                    "⏳"
+                } else {
+                    "▫️"
+                };
+                card += &format!("{} {}\n", mark, Text::Step(step).localize(lang));
+            }
+        }
+        Ok(None) => card += &format!("{}\n", Text::NothingToShow.localize(lang)),
+        Err(e) => card += &format!("{}\n", Text::CantMakeSense(e.to_string()).localize(lang)),
+    }
+
+    let stages = [
+        (Text::WrittenTest, ap.written_test_info.status),
+        (Text::Assessment, ap.assessment_info.status),
+    ];
+    if stages.iter().any(|&(_, status)| status != 0) {
+        card += "\n";
+    }
+    for (label, status) in stages.into_iter().filter(|&(_, status)| status != 0) {
+        card += &format!(
+            "{}: {}\n",
+            label.localize(lang),
+            Text::StageState(status).localize(lang)
+        );
+    }
+
+    if let Some(since) = since_change {
+        card += &format!("\n<i>{}</i>", Text::LastChanged(since).localize(lang));
+    }
+
+    let assessment = Url::parse(&ap.assessment_info.test_address)
+        .ok()
+        .filter(|url| ap.assessment_info.status == 2 && url.scheme() == "https");
+    let button = match assessment {
+        Some(url) => InlineKeyboardButton::url(Text::TakeAssessment.localize(lang), url),
+        None => InlineKeyboardButton::url(
+            Text::OpenJoinQq.localize(lang),
+            JOIN_QQ.parse().expect("Tencent becomes no URL"),
+        ),
+    };
+    (
+        card.trim_end().to_string(),
+        InlineKeyboardMarkup::new([[button]]),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use teloxide::types::InlineKeyboardButtonKind;
+
+    fn progress() -> ApplicationProgress {
+        serde_json::from_value(serde_json::json!({
+            "resumeId": 1,
+            "currentStatus": { "status": 1, "applyProcessType": 1 },
+            "assessmentInfo": {
+                "status": 2,
+                "testAddress": "https://exam.example.com/t/1",
+                "mobileTail": "0000",
+            },
+            "positionInfo": {
+                "applyPositionTxt": "Backend Engineer",
+                "interviewPositionTxt": "R&D <Infra>",
+                "subDirectionIdTxt": "",
+            },
+            "resumeStatus": { "status": 3, "isPublic": 1 },
+            "writtenTestInfo": { "status": 3, "itemList": [] },
+            "campusRecruitOne": {
+                "id": 1,
+                "itemList": [{ "stepId": 1, "status": 3 }, { "stepId": 2, "status": 2 }],
+                "recruitType": 1,
+                "typeName": "",
+            },
+            "campusRecruitTwo": { "itemList": [], "bgid": 1 },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn renders_status_card() {
+        let mut ap = progress();
+        ap.assessment_info.status = 3;
+        let (card, keyboard) = status_card(&ap, Some(Duration::from_secs(3 * 60 * 60)), Lang::En);
+        assert_eq!(
+            card,
+            "<b>Backend Engineer</b>\n\
+             Interview position: R&amp;D &lt;Infra&gt;\n\
+             \n\
+             ✅ CV delivery\n\
+             ✅ Group interview\n\
+             ⏳ Preliminary interview\n\
+             ▫️ Secondary interview\n\
+             ▫️ HR interview\n\
+             ▫️ Employer assessment\n\
+             ▫️ Employee confirmation\n\
+             ▫️ Offer confirmation\n\
+             ▫️ Completed\n\
+             \n\
+             Written test: done\n\
+             Assessment: done\n\
+             \n\
+             <i>Last changed 3 hours ago.</i>"
+        );
+        let button = &keyboard.inline_keyboard[0][0];
+        assert_eq!(button.text, "Open join.qq.com");
+
+        let (card, _) = status_card(&ap, None, Lang::ZhCn);
+        assert!(card.contains("面试岗位: R&amp;D &lt;Infra&gt;\n"));
+        assert!(card.contains("⏳ 初试\n"));
+        assert!(card.ends_with("测评: 已完成"));
+    }
+
+    #[test]
+    fn links_to_pending_assessment() {
+        let (card, keyboard) = status_card(&progress(), None, Lang::En);
+        // the assessment takes over from the interviews while it's on
+        assert!(card.contains("✅ CV delivery\n▫️ Group interview"));
+        assert!(card.ends_with("Assessment: in progress"));
+        let button = &keyboard.inline_keyboard[0][0];
+        assert_eq!(button.text, "Take the assessment");
+        assert!(matches!(
+            &button.kind,
+            InlineKeyboardButtonKind::Url(url) if url.as_str() == "https://exam.example.com/t/1"
+        ));
+    }
+}
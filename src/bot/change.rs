@@ -1,23 +1,120 @@
-use std::fmt::{Display, Formatter};
+use crate::bot::i18n::{Lang, Text};
+use crate::repo::ledger::Snapshot;
+use crate::repo::settings::NotificationFilter;
 use crate::tencent::model::ApplicationProgress;
+use crate::tencent::progress::Step;
 
 #[derive(Clone)]
 pub enum StatusChange {
-    Progress(ApplicationProgress),
-    Expiry
+    Progress(Box<ApplicationProgress>),
 }
 
-impl Display for StatusChange {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+impl StatusChange {
+    /// Whether the change asks for action soon, like an assessment to take, and is
+    /// worth disturbing quiet hours for.
+    pub fn is_urgent(&self) -> bool {
+        match self {
+            StatusChange::Progress(ap) => matches!(
+                ap.get_current_step(),
+                Ok(Some(Step::Examination | Step::WrittenTest))
+            ),
+        }
+    }
+
+    /// Whether the filter lets the change through, given the progress it replaces.
+    pub fn passes(&self, filter: NotificationFilter, before: Option<&ApplicationProgress>) -> bool {
+        match self {
+            StatusChange::Progress(ap) => {
+                let step = ap.get_current_step().ok().flatten();
+                match filter {
+                    NotificationFilter::All => true,
+                    NotificationFilter::StepChanges => {
+                        before.and_then(|b| b.get_current_step().ok().flatten()) != step
+                    }
+                    NotificationFilter::From(from) => step.is_some_and(|step| step >= from),
+                    NotificationFilter::Assessments => self.is_urgent(),
+                }
+            }
+        }
+    }
+}
+
+impl StatusChange {
+    /// Where the application stands after the change, next to where it stood
+    /// `before`.
+    pub fn snapshot(&self, before: Option<&ApplicationProgress>) -> Snapshot {
+        match self {
+            StatusChange::Progress(ap) => Snapshot {
+                step: ap.get_current_step().ok().flatten(),
+                previous_step: before.and_then(|b| b.get_current_step().ok().flatten()),
+                written_test_status: ap.written_test_info.status,
+                assessment_status: ap.assessment_info.status,
+                position: ap.position_info.apply_position_txt.clone(),
+            }
+        }
+    }
+
+    /// One line telling what the change is about, as notifications put it.
+    pub fn summary(&self, lang: Lang) -> String {
         match self {
             StatusChange::Progress(ap) => {
                 match ap.get_current_step() {
-                    Ok(Some(step)) => write!(f, "{:?}", step),
-                    Ok(None) => write!(f, "empty"),
-                    Err(e) => write!(f, "{} error", e)
+                    Ok(Some(step)) => Text::Step(step).localize(lang),
+                    Ok(None) => Text::NoStep.localize(lang),
+                    Err(e) => Text::UnknownProgress(e.to_string()).localize(lang)
                 }
             }
-            StatusChange::Expiry => write!(f, "token expiry")
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Progress with the given resume status, assessment status and the status of
+    /// each first round interview step.
+    fn progress(resume: u8, assessment: u8, interviews: &[(u32, u8)]) -> ApplicationProgress {
+        let items: Vec<_> = interviews
+            .iter()
+            .map(|&(id, status)| serde_json::json!({ "stepId": id, "status": status }))
+            .collect();
+        serde_json::from_value(serde_json::json!({
+            "resumeId": 1,
+            "currentStatus": { "status": 1, "applyProcessType": 1 },
+            "assessmentInfo": { "status": assessment, "testAddress": "", "mobileTail": "" },
+            "positionInfo": { "applyPositionTxt": "Engineer" },
+            "resumeStatus": { "status": resume, "isPublic": 1 },
+            "writtenTestInfo": { "status": 0, "itemList": [] },
+            "campusRecruitOne": { "id": 1, "itemList": items, "recruitType": 1, "typeName": "" },
+            "campusRecruitTwo": { "itemList": [], "bgid": 1 },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn filters_by_rule() {
+        let delivered = progress(2, 0, &[]);
+        let examination = progress(3, 2, &[]);
+        let hr_interview = progress(3, 3, &[(1, 3), (5, 2)]);
+        let hr_interview_passed = progress(3, 3, &[(1, 3), (5, 2), (2, 3)]);
+        let change = |ap: &ApplicationProgress| StatusChange::Progress(Box::new(ap.clone()));
+
+        let passing = |filter| {
+            [
+                (&examination, &delivered),
+                (&hr_interview, &examination),
+                (&hr_interview_passed, &hr_interview),
+            ]
+            .map(|(after, before)| change(after).passes(filter, Some(before)))
+        };
+        assert_eq!(passing(NotificationFilter::All), [true, true, true]);
+        assert_eq!(passing(NotificationFilter::StepChanges), [true, true, false]);
+        assert_eq!(
+            passing(NotificationFilter::From(Step::HrInterview)),
+            [false, true, true]
+        );
+        assert_eq!(passing(NotificationFilter::Assessments), [true, false, false]);
+        assert!(change(&delivered).passes(NotificationFilter::StepChanges, None));
+    }
+}
@@ -10,6 +10,7 @@ pub struct ClientCollection {
 }
 
 impl ClientCollection {
+    #[cfg(test)]
     pub fn new() -> Self {
         Self {
             inner: HashMap::new(),
@@ -29,8 +30,16 @@ impl ClientCollection {
         Self { inner: clients }
     }
     
-    pub async fn get(&self, acc: AccountIndex) -> Option<&Mutex<Client>> {
-        self.inner.get(&acc).map(|c| c.as_ref())
+    pub fn contains(&self, acc: AccountIndex) -> bool {
+        self.inner.contains_key(&acc)
+    }
+
+    pub fn accounts(&self) -> Vec<AccountIndex> {
+        self.inner.keys().copied().collect()
+    }
+
+    pub async fn get(&self, acc: AccountIndex) -> Option<Arc<Mutex<Client>>> {
+        self.inner.get(&acc).cloned()
     }
     
     pub async fn insert(&mut self, acc: AccountIndex, client: Client) {
@@ -1,3 +1,7 @@
+use crate::bot::i18n::Lang;
+use crate::repo::settings::NotificationFilter;
+use crate::tencent::progress::Step;
+use std::fmt::{Display, Formatter};
 use teloxide::macros::BotCommands;
 
 #[derive(BotCommands, Clone)]
@@ -5,12 +9,122 @@ use teloxide::macros::BotCommands;
 pub enum Command {
     #[command(description = "display this text.")]
     Help,
-    #[command(description = "revoke your token if applicable, replace it with a new one, which is the UserInfo cookie from the recruiter's website.")]
+    #[command(description = "sign in with the UserInfo cookie from the recruiter's website, which the bot asks for and deletes once read. Replaces the stored token, if any.")]
     SignIn { token: String },
     #[command(description = "get the current application state.")]
     Get,
-    #[command(description = "set the polling interval between which the application progress is queried.")]
-    Interval,
+    #[command(description = "check the application right away and reset the polling schedule.")]
+    Refresh,
+    #[command(description = "set the polling interval between which the application progress is queried, e.g. /interval 45m. Without an interval, show the current one and choose from a keyboard.")]
+    Interval { interval: String },
+    #[command(description = "let the polling interval adapt to how active your application is, between a minimum and a maximum, e.g. /adaptive 5m 12h. Without bounds, return to the fixed interval.")]
+    Adaptive { bounds: String },
+    #[command(description = "hold notifications back during quiet hours, e.g. /quiet 23:00 07:00 +08:00. Append \"urgent\" to let assessments through anyway. Without arguments, turn quiet hours off.")]
+    Quiet { hours: String },
+    #[command(description = "choose what you are notified about, like only step advances or assessments.")]
+    Notify,
+    #[command(description = "forward notifications as signed JSON to an HTTPS URL, e.g. /webhook https://example.com/qazer. Without a URL, stop forwarding.")]
+    Webhook { url: String },
+    #[command(description = "email notifications as well, e.g. /email me@example.com, then confirm with the code sent there. Without an address, stop emailing.")]
+    Email { address: String },
+    #[command(description = "choose the language the bot speaks.")]
+    Language,
     #[command(description = "revoke your token and stop receiving notifications.")]
     SignOut,
+    #[command(description = "erase your token, progress history and settings.")]
+    ForgetMe,
+}
+
+/// Payload of the inline keyboard buttons, carried as callback data.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Callback {
+    Interval(u32),
+    ForgetMe(bool),
+    Notify(NotificationFilter),
+    /// The language picked, or `None` to follow the Telegram client.
+    Language(Option<Lang>),
+}
+
+impl Callback {
+    pub fn parse(data: &str) -> Option<Self> {
+        match data.split_once(':') {
+            Some(("interval", min)) => min.parse().ok().map(Callback::Interval),
+            Some(("forgetme", "yes")) => Some(Callback::ForgetMe(true)),
+            Some(("forgetme", "no")) => Some(Callback::ForgetMe(false)),
+            Some(("notify", filter)) => parse_filter(filter).map(Callback::Notify),
+            Some(("language", "auto")) => Some(Callback::Language(None)),
+            Some(("language", tag)) => Lang::ALL
+                .into_iter()
+                .find(|lang| lang.tag() == tag)
+                .map(|lang| Callback::Language(Some(lang))),
+            // keyboards sent before callbacks were prefixed carry bare minutes
+            None => data.parse().ok().map(Callback::Interval),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Callback {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Callback::Interval(min) => write!(f, "interval:{}", min),
+            Callback::ForgetMe(true) => write!(f, "forgetme:yes"),
+            Callback::ForgetMe(false) => write!(f, "forgetme:no"),
+            Callback::Notify(NotificationFilter::All) => write!(f, "notify:all"),
+            Callback::Notify(NotificationFilter::StepChanges) => write!(f, "notify:steps"),
+            Callback::Notify(NotificationFilter::From(step)) => write!(f, "notify:from:{:?}", step),
+            Callback::Notify(NotificationFilter::Assessments) => write!(f, "notify:assessments"),
+            Callback::Language(Some(lang)) => write!(f, "language:{}", lang.tag()),
+            Callback::Language(None) => write!(f, "language:auto"),
+        }
+    }
+}
+
+fn parse_filter(data: &str) -> Option<NotificationFilter> {
+    match data.split_once(':') {
+        Some(("from", name)) => Step::ALL
+            .into_iter()
+            .find(|step| format!("{:?}", step) == name)
+            .map(NotificationFilter::From),
+        Some(_) => None,
+        None => match data {
+            "all" => Some(NotificationFilter::All),
+            "steps" => Some(NotificationFilter::StepChanges),
+            "assessments" => Some(NotificationFilter::Assessments),
+            _ => None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Callback;
+    use crate::bot::i18n::Lang;
+    use crate::repo::settings::NotificationFilter;
+    use crate::tencent::progress::Step;
+
+    #[test]
+    fn callback_round_trips() {
+        for callback in [
+            Callback::Interval(30),
+            Callback::ForgetMe(true),
+            Callback::ForgetMe(false),
+            Callback::Notify(NotificationFilter::All),
+            Callback::Notify(NotificationFilter::StepChanges),
+            Callback::Notify(NotificationFilter::From(Step::HrInterview)),
+            Callback::Notify(NotificationFilter::Assessments),
+            Callback::Language(Some(Lang::ZhCn)),
+            Callback::Language(None),
+        ] {
+            assert_eq!(Callback::parse(&callback.to_string()), Some(callback));
+        }
+    }
+
+    #[test]
+    fn accepts_legacy_interval_data() {
+        assert_eq!(Callback::parse("60"), Some(Callback::Interval(60)));
+        assert_eq!(Callback::parse("forgetme:maybe"), None);
+        assert_eq!(Callback::parse("notify:from:Lunch"), None);
+        assert_eq!(Callback::parse("language:fr"), None);
+    }
 }
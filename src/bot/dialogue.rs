@@ -0,0 +1,14 @@
+use serde::{Deserialize, Serialize};
+use teloxide::dispatching::dialogue::{Dialogue, ErasedStorage};
+
+/// Where a chat stands in a conversation the bot leads.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum State {
+    #[default]
+    Start,
+    /// /signin asked for the cookie, which the next message carries.
+    ReceiveToken,
+}
+
+pub type SignInDialogue = Dialogue<State, ErasedStorage<State>>;
@@ -0,0 +1,791 @@
+use crate::bot::cmd::Command;
+use crate::bot::notify::webhook::SIGNATURE_HEADER;
+use crate::repo::model::AccountIndex;
+use crate::repo::settings::{AdaptivePolling, NotificationFilter, QuietHours, UserSettings};
+use crate::tencent::progress::Step;
+use crate::watch::recurrence::format_duration;
+use std::time::Duration;
+use teloxide::utils::command::BotCommands;
+
+/// Languages the bot speaks.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Lang {
+    #[default]
+    En,
+    ZhCn,
+}
+
+impl Lang {
+    pub const ALL: [Lang; 2] = [Lang::En, Lang::ZhCn];
+
+    /// Picks the catalog closest to a language tag like `zh-hans` or `en-US`,
+    /// falling back to English.
+    pub fn from_tag(tag: &str) -> Self {
+        let primary = tag.split(['-', '_']).next().unwrap_or_default();
+        if primary.eq_ignore_ascii_case("zh") {
+            Lang::ZhCn
+        } else {
+            Lang::En
+        }
+    }
+
+    pub fn tag(self) -> &'static str {
+        match self {
+            Lang::En => "en",
+            Lang::ZhCn => "zh-CN",
+        }
+    }
+
+    /// The language's name in itself.
+    pub fn name(self) -> &'static str {
+        match self {
+            Lang::En => "English",
+            Lang::ZhCn => "简体中文",
+        }
+    }
+
+    /// The language the user picked, or else that of their Telegram client.
+    pub fn of(settings: &UserSettings) -> Self {
+        settings
+            .language
+            .as_deref()
+            .or(settings.client_language.as_deref())
+            .map_or_else(Lang::default, Lang::from_tag)
+    }
+}
+
+/// Everything the bot says, rendered with [`Text::localize`].
+#[derive(Clone, Debug)]
+pub enum Text {
+    Help,
+    NoUser,
+    NoToken,
+    /// Carries the account to tell the administrator.
+    DatabaseError(AccountIndex),
+    DatabaseReadError(AccountIndex),
+    FetchFailed(String),
+    InvalidClick,
+    InvalidCallbackData,
+
+    SignInPrompt,
+    TokenExpected,
+    DeleteTokenYourself,
+    InvalidToken(String),
+    TokenUpdated,
+    SignedOut,
+    NothingToSignOut,
+    ForgetMePrompt,
+    EraseEverything,
+    Cancel,
+    NothingErased,
+    AllErased,
+
+    /// How the account is polled, shown above the interval keyboard.
+    IntervalStatus {
+        interval: Option<Duration>,
+        adaptive: Option<AdaptivePolling>,
+        /// Time until the next poll, if one is scheduled.
+        next: Option<Duration>,
+        min: Duration,
+        max: Duration,
+    },
+    Minutes(u32),
+    TurnOff,
+    IntervalSet(Duration),
+    IntervalUsage,
+    /// Bounds the interval has to fall within.
+    IntervalOutOfRange(Duration, Duration),
+    PollingDisabled,
+    AdaptiveUsage,
+    AdaptiveBounds,
+    /// Bounds the interval adapts between.
+    AdaptiveOn(Duration, Duration),
+    AdaptiveOff,
+    /// Carries why the hours couldn't be parsed.
+    InvalidQuietHours(String),
+    QuietOn(QuietHours),
+    QuietOff,
+
+    NothingChanged,
+    FilteredOut,
+    /// Seconds until the next refresh is allowed.
+    RefreshCooldown(u64),
+    /// A poll of the account was under way when /refresh came in.
+    RefreshRunning,
+    Expired,
+    Reminder,
+
+    NotifyPrompt(NotificationFilter),
+    NotifySet(NotificationFilter),
+    FilterButton(NotificationFilter),
+
+    WebhookUsage,
+    /// The webhook URL leads to a loopback or private address, or doesn't resolve.
+    WebhookNotPublic,
+    WebhookSet {
+        url: String,
+        secret: String,
+    },
+    WebhookOff,
+
+    EmailUnavailable,
+    EmailOff,
+    EmailUsage,
+    EmailSendFailed(String),
+    EmailCodeSent(String),
+    EmailConfirmed(String),
+    EmailCodeMismatch,
+    EmailTooManyCodes(String),
+    EmailNothingPending,
+    /// Time until another confirmation code may be sent.
+    EmailTooManySends(Duration),
+
+    /// Subject of an email announcing one change, by its summary.
+    LetterSubject(String),
+    /// Subject of an email announcing this many changes.
+    LetterSubjectMany(usize),
+    LetterIntro,
+    LetterUrgent,
+    CodeSubject,
+    /// The code and the command giving it back, already marked up for the body
+    /// they go in.
+    CodeBody(String, String),
+
+    DigestStep(Step),
+    DigestNoStep,
+    DigestUnknown(String),
+
+    Step(Step),
+    /// Progress that isn't at any step.
+    NoStep,
+    /// Carries why the progress couldn't be made sense of.
+    UnknownProgress(String),
+    ProgressUpdate(String),
+    ProgressUpdates(Vec<String>),
+
+    InterviewPosition,
+    SubDirection,
+    NothingToShow,
+    CantMakeSense(String),
+    WrittenTest,
+    Assessment,
+    /// Status of a written test or an assessment, which isn't 0.
+    StageState(u8),
+    LastChanged(Duration),
+    TakeAssessment,
+    OpenJoinQq,
+
+    /// The explicit choice if any, and the language in use.
+    LanguagePrompt(Option<Lang>, Lang),
+    FollowTelegram,
+    LanguageSet(Lang),
+    LanguageFollowing(Lang),
+}
+
+impl Text {
+    pub fn localize(&self, lang: Lang) -> String {
+        match lang {
+            Lang::En => self.en(),
+            Lang::ZhCn => self.zh_cn(),
+        }
+    }
+
+    fn en(&self) -> String {
+        match self {
+            Text::Help => Command::descriptions().to_string(),
+            Text::NoUser => "No user info bound to this context.".into(),
+            Text::NoToken => {
+                "No token associated with current context. Use the /signin command to get started."
+                    .into()
+            }
+            Text::DatabaseError(acc) => format!(
+                "Failed to update database. Please contact the system administrator, with your user id {}.",
+                acc
+            ),
+            Text::DatabaseReadError(acc) => format!(
+                "Failed to read database. Please contact the system administrator, with your user id {}.",
+                acc
+            ),
+            Text::FetchFailed(e) => format!("Fetch failed because {}", e),
+            Text::InvalidClick => "Your invalid click has no effect.".into(),
+            Text::InvalidCallbackData => {
+                "Your message carries invalid data thus has no effect.".into()
+            }
+
+            Text::SignInPrompt => "Send the UserInfo cookie of join.qq.com as your next message. To find it, sign in at https://join.qq.com in a desktop browser, open the developer tools, and copy the value of the UserInfo cookie from the storage or application tab. The message is deleted as soon as it arrives. Send any command to cancel.".into(),
+            Text::TokenExpected => {
+                "Send the cookie as text, or any command to cancel.".into()
+            }
+            Text::DeleteTokenYourself => {
+                "Couldn't delete your message. Delete it yourself, as it holds your token.".into()
+            }
+            Text::InvalidToken(e) => format!("Invalid token: {}. Use /signin to try again.", e),
+            Text::TokenUpdated => "Token has been updated.".into(),
+            Text::SignedOut => "Revoked previously stored token and stopped polling.".into(),
+            Text::NothingToSignOut => "No stored token. This operation carries no effect.".into(),
+            Text::ForgetMePrompt => {
+                "This erases your token, cached progress and settings, and can't be undone. Continue?"
+                    .into()
+            }
+            Text::EraseEverything => "Erase everything".into(),
+            Text::Cancel => "Cancel".into(),
+            Text::NothingErased => "Nothing has been erased.".into(),
+            Text::AllErased => "Your token, progress history and settings have been erased.".into(),
+
+            Text::IntervalStatus {
+                interval,
+                adaptive,
+                next,
+                min,
+                max,
+            } => {
+                let mut text = match (interval, adaptive) {
+                    (_, Some(a)) => format!(
+                        "Polling adapts between {} and {}.",
+                        format_duration(a.min),
+                        format_duration(a.max)
+                    ),
+                    (Some(interval), None) => format!("Polling every {}.", format_duration(*interval)),
+                    (None, None) => "Polling is off.".into(),
+                };
+                match next {
+                    Some(next) if next.as_secs() < 60 => text += " Next poll in under a minute.",
+                    Some(next) => text += &format!(" Next poll in {}.", format_duration(whole_minutes(*next))),
+                    None => {}
+                }
+                text += &format!(
+                    "\nChoose an interval, or send one between {} and {} like /interval 45m.",
+                    format_duration(*min),
+                    format_duration(*max)
+                );
+                text
+            }
+            Text::Minutes(min) => format!("{}min", min),
+            Text::TurnOff => "Turn Off".into(),
+            Text::IntervalSet(interval) => format!(
+                "Polling interval has been updated to {}.",
+                format_duration(*interval)
+            ),
+            Text::IntervalUsage => "Give an interval like /interval 45m or /interval 2h, or /interval off to stop polling.".into(),
+            Text::IntervalOutOfRange(min, max) => format!(
+                "The interval has to be between {} and {}.",
+                format_duration(*min),
+                format_duration(*max)
+            ),
+            Text::PollingDisabled => "Polling has been disabled.".into(),
+            Text::AdaptiveUsage => {
+                "Give the minimum and maximum interval, e.g. /adaptive 5m 12h.".into()
+            }
+            Text::AdaptiveBounds => {
+                "The minimum must be positive and no greater than the maximum.".into()
+            }
+            Text::AdaptiveOn(min, max) => {
+                format!(
+                    "Polling now adapts between {} and {}.",
+                    format_duration(*min),
+                    format_duration(*max)
+                )
+            }
+            Text::AdaptiveOff => {
+                "Polling is back to the fixed interval. Use /interval to choose it.".into()
+            }
+            Text::InvalidQuietHours(reason) => format!("Invalid quiet hours: {}.", reason),
+            Text::QuietOn(q) => format!(
+                "Notifications are held from {:02}:{:02} to {:02}:{:02}{}.",
+                q.start / 60,
+                q.start % 60,
+                q.end / 60,
+                q.end % 60,
+                if q.urgent { ", except urgent ones" } else { "" }
+            ),
+            Text::QuietOff => "Quiet hours have been turned off.".into(),
+
+            Text::NothingChanged => "Nothing has changed since the last check.".into(),
+            Text::FilteredOut => {
+                "Your progress has changed, but not in a way /notify lets through.".into()
+            }
+            Text::RefreshCooldown(secs) => {
+                format!("Checked just now. Try again in {} seconds.", secs)
+            }
+            Text::RefreshRunning => "A check is already running. Try again in a moment.".into(),
+            Text::Expired => "Your token has expired, so polling is paused. Use /signin to provide a new one and polling resumes, or /signout to stop the reminders.".into(),
+            Text::Reminder => "Your token is still expired. Use /signin to provide a new one, or /signout to stop these reminders.".into(),
+
+            Text::NotifyPrompt(filter) => format!(
+                "You are notified about {}. Choose what to be notified about.",
+                en_filter(*filter)
+            ),
+            Text::NotifySet(filter) => format!("You will be notified about {}.", en_filter(*filter)),
+            Text::FilterButton(NotificationFilter::All) => "Everything".into(),
+            Text::FilterButton(NotificationFilter::StepChanges) => "Step advances".into(),
+            Text::FilterButton(NotificationFilter::Assessments) => {
+                "Assessments and written tests".into()
+            }
+            Text::FilterButton(NotificationFilter::From(step)) => {
+                format!("From {}", Text::Step(*step).en())
+            }
+
+            Text::WebhookUsage => "Give an HTTPS URL, e.g. /webhook https://example.com/qazer.".into(),
+            Text::WebhookNotPublic => "The webhook has to resolve to a public address.".into(),
+            Text::WebhookSet { url, secret } => format!(
+                "Notifications are forwarded to {}. Each one is signed in the {} header with HMAC-SHA256 under the secret {}",
+                url, SIGNATURE_HEADER, secret
+            ),
+            Text::WebhookOff => "Notifications are no longer forwarded.".into(),
+
+            Text::EmailUnavailable => "Email isn't set up on this bot.".into(),
+            Text::EmailOff => "Notifications are no longer emailed.".into(),
+            Text::EmailUsage => "Give an email address, e.g. /email me@example.com.".into(),
+            Text::EmailSendFailed(address) => format!(
+                "Couldn't send email to {}. Check the address and try again.",
+                address
+            ),
+            Text::EmailCodeSent(address) => format!(
+                "A confirmation code was sent to {}. Send it back with /email followed by the code.",
+                address
+            ),
+            Text::EmailConfirmed(address) => {
+                format!("Confirmed. Notifications are also emailed to {}.", address)
+            }
+            Text::EmailCodeMismatch => {
+                "That code doesn't match. Check the latest email and try again.".into()
+            }
+            Text::EmailTooManyCodes(address) => format!(
+                "Too many wrong codes. Send /email {} to get a new one.",
+                address
+            ),
+            Text::EmailNothingPending => {
+                "No address is waiting for confirmation. Send /email followed by your address first."
+                    .into()
+            }
+            Text::EmailTooManySends(wait) => format!(
+                "Too many confirmation codes were sent lately. Try again in {}.",
+                format_duration(*wait)
+            ),
+
+            Text::LetterSubject(summary) => format!("Qazer: {}", summary),
+            Text::LetterSubjectMany(count) => format!("Qazer: {} progress updates", count),
+            Text::LetterIntro => "Your application has progressed:".into(),
+            Text::LetterUrgent => "needs your attention soon".into(),
+            Text::CodeSubject => "Qazer confirmation code".into(),
+            Text::CodeBody(code, command) => format!(
+                "Your confirmation code is {}. Send {} to the bot to receive notifications at this address.",
+                code, command
+            ),
+
+            Text::DigestStep(step) => format!(
+                "Daily digest: your application is at {}.",
+                Text::Step(*step).en()
+            ),
+            Text::DigestNoStep => "Daily digest: your application has no current step.".into(),
+            Text::DigestUnknown(e) => format!("Daily digest: your application is at an {}.", e),
+
+            Text::Step(step) => match step {
+                Step::CvDeliverance => "CV delivery",
+                Step::Examination => "Examination",
+                Step::WrittenTest => "Written test",
+                Step::GroupInterview => "Group interview",
+                Step::PreliminaryInterview => "Preliminary interview",
+                Step::SecondaryInterview => "Secondary interview",
+                Step::HrInterview => "HR interview",
+                Step::EmployerAssessment => "Employer assessment",
+                Step::EmployeeConfirmation => "Employee confirmation",
+                Step::OfferConfirmation => "Offer confirmation",
+                Step::SignUp => "Sign-up",
+                Step::Completed => "Completed",
+            }
+            .into(),
+            Text::NoStep => "empty".into(),
+            Text::UnknownProgress(e) => format!("{} error", e),
+            Text::ProgressUpdate(summary) => format!("Progress update: {}", summary),
+            Text::ProgressUpdates(summaries) => summaries.iter().fold(
+                "Progress updates since the last message:".to_string(),
+                |text, summary| format!("{}\n- {}", text, summary),
+            ),
+
+            Text::InterviewPosition => "Interview position".into(),
+            Text::SubDirection => "Sub-direction".into(),
+            Text::NothingToShow => "Nothing to show yet. Check the web page for more info.".into(),
+            Text::CantMakeSense(e) => format!("Can't make sense of the progress because {}.", e),
+            Text::WrittenTest => "Written test".into(),
+            Text::Assessment => "Assessment".into(),
+            Text::StageState(status) => match status {
+                1 => "not started".into(),
+                2 => "in progress".into(),
+                3 => "done".into(),
+                other => format!("status {}", other),
+            },
+            Text::LastChanged(since) => {
+                let minutes = since.as_secs() / 60;
+                let (count, unit) = match minutes {
+                    0 => return "Last changed just now.".into(),
+                    1..60 => (minutes, "minute"),
+                    60..1440 => (minutes / 60, "hour"),
+                    _ => (minutes / 1440, "day"),
+                };
+                let plural = if count == 1 { "" } else { "s" };
+                format!("Last changed {} {}{} ago.", count, unit, plural)
+            }
+            Text::TakeAssessment => "Take the assessment".into(),
+            Text::OpenJoinQq => "Open join.qq.com".into(),
+
+            Text::LanguagePrompt(None, lang) => format!(
+                "Messages are in {}, following your Telegram client. Choose a language.",
+                lang.name()
+            ),
+            Text::LanguagePrompt(Some(lang), _) => {
+                format!("Messages are in {}. Choose a language.", lang.name())
+            }
+            Text::FollowTelegram => "Follow Telegram".into(),
+            Text::LanguageSet(lang) => format!("Messages are now in {}.", lang.name()),
+            Text::LanguageFollowing(lang) => format!(
+                "Messages now follow your Telegram client, currently {}.",
+                lang.name()
+            ),
+        }
+    }
+
+    fn zh_cn(&self) -> String {
+        match self {
+            Text::Help => [
+                "/help — 显示本帮助。",
+                "/signin — 用招聘网站上的 UserInfo Cookie 登录，机器人会询问 Cookie 并在读取后删除该消息。已保存的令牌（如有）将被替换。",
+                "/get — 查看当前的申请进度。",
+                "/refresh — 立即检查申请进度，并重新开始轮询计时。",
+                "/interval — 设置查询申请进度的时间间隔，例如 /interval 45m。不带参数则显示当前间隔并从键盘中选择。",
+                "/adaptive — 让轮询间隔随申请的活跃程度在最小值和最大值之间调整，例如 /adaptive 5m 12h。不带参数则恢复固定间隔。",
+                "/quiet — 在免打扰时段内暂缓通知，例如 /quiet 23:00 07:00 +08:00。末尾加上 \"urgent\" 可让测评照常通知。不带参数则关闭免打扰。",
+                "/notify — 选择接收哪些通知，例如仅步骤推进或测评。",
+                "/webhook — 将通知以签名的 JSON 转发到 HTTPS 地址，例如 /webhook https://example.com/qazer。不带地址则停止转发。",
+                "/email — 同时通过邮件接收通知，例如 /email me@example.com，然后用发到邮箱的验证码确认。不带地址则停止发送邮件。",
+                "/language — 选择机器人使用的语言。",
+                "/signout — 撤销令牌并停止接收通知。",
+                "/forgetme — 清除你的令牌、进度记录和设置。",
+            ]
+            .join("\n"),
+            Text::NoUser => "当前对话没有绑定用户信息。".into(),
+            Text::NoToken => "当前对话没有关联令牌。请使用 /signin 命令开始。".into(),
+            Text::DatabaseError(acc) => {
+                format!("更新数据库失败。请联系系统管理员，并提供你的用户 ID {}。", acc)
+            }
+            Text::DatabaseReadError(acc) => {
+                format!("读取数据库失败。请联系系统管理员，并提供你的用户 ID {}。", acc)
+            }
+            Text::FetchFailed(e) => format!("获取失败：{}", e),
+            Text::InvalidClick => "无效的点击，没有任何效果。".into(),
+            Text::InvalidCallbackData => "消息携带的数据无效，没有任何效果。".into(),
+
+            Text::SignInPrompt => "请在下一条消息中发送 join.qq.com 的 UserInfo Cookie。获取方法：在电脑浏览器中登录 https://join.qq.com，打开开发者工具，在存储或应用程序标签页中复制 UserInfo Cookie 的值。消息收到后会立即删除。发送任意命令即可取消。".into(),
+            Text::TokenExpected => "请以文本形式发送 Cookie，或发送任意命令取消。".into(),
+            Text::DeleteTokenYourself => "无法删除你的消息。它包含你的令牌，请自行删除。".into(),
+            Text::InvalidToken(e) => format!("令牌无效：{}。使用 /signin 重试。", e),
+            Text::TokenUpdated => "令牌已更新。".into(),
+            Text::SignedOut => "已撤销保存的令牌并停止轮询。".into(),
+            Text::NothingToSignOut => "没有保存的令牌，此操作没有效果。".into(),
+            Text::ForgetMePrompt => "这将清除你的令牌、缓存的进度和设置，且无法撤销。是否继续？".into(),
+            Text::EraseEverything => "全部清除".into(),
+            Text::Cancel => "取消".into(),
+            Text::NothingErased => "没有清除任何数据。".into(),
+            Text::AllErased => "你的令牌、进度记录和设置已清除。".into(),
+
+            Text::IntervalStatus {
+                interval,
+                adaptive,
+                next,
+                min,
+                max,
+            } => {
+                let mut text = match (interval, adaptive) {
+                    (_, Some(a)) => format!(
+                        "轮询间隔在 {} 到 {} 之间自动调整。",
+                        format_duration(a.min),
+                        format_duration(a.max)
+                    ),
+                    (Some(interval), None) => format!("每 {} 轮询一次。", format_duration(*interval)),
+                    (None, None) => "轮询已关闭。".into(),
+                };
+                match next {
+                    Some(next) if next.as_secs() < 60 => text += "下次轮询将在一分钟内进行。",
+                    Some(next) => text += &format!("下次轮询在 {} 后。", format_duration(whole_minutes(*next))),
+                    None => {}
+                }
+                text += &format!(
+                    "\n请选择间隔，或发送 {} 到 {} 之间的间隔，例如 /interval 45m。",
+                    format_duration(*min),
+                    format_duration(*max)
+                );
+                text
+            }
+            Text::Minutes(min) => format!("{}分钟", min),
+            Text::TurnOff => "关闭".into(),
+            Text::IntervalSet(interval) => {
+                format!("轮询间隔已更新为 {}。", format_duration(*interval))
+            }
+            Text::IntervalUsage => "请给出间隔，例如 /interval 45m 或 /interval 2h，或用 /interval off 停止轮询。".into(),
+            Text::IntervalOutOfRange(min, max) => format!(
+                "间隔必须在 {} 到 {} 之间。",
+                format_duration(*min),
+                format_duration(*max)
+            ),
+            Text::PollingDisabled => "轮询已关闭。".into(),
+            Text::AdaptiveUsage => "请给出最小和最大间隔，例如 /adaptive 5m 12h。".into(),
+            Text::AdaptiveBounds => "最小值必须为正数，且不大于最大值。".into(),
+            Text::AdaptiveOn(min, max) => {
+                format!(
+                    "轮询间隔现在会在 {} 到 {} 之间调整。",
+                    format_duration(*min),
+                    format_duration(*max)
+                )
+            }
+            Text::AdaptiveOff => "轮询已恢复为固定间隔。使用 /interval 进行选择。".into(),
+            Text::InvalidQuietHours(_) => {
+                "免打扰时段无效。格式如 /quiet 23:00 07:00 +08:00，末尾可加上 \"urgent\"。".into()
+            }
+            Text::QuietOn(q) => format!(
+                "{:02}:{:02} 至 {:02}:{:02} 期间的通知将被暂缓{}。",
+                q.start / 60,
+                q.start % 60,
+                q.end / 60,
+                q.end % 60,
+                if q.urgent { "，紧急通知除外" } else { "" }
+            ),
+            Text::QuietOff => "免打扰已关闭。".into(),
+
+            Text::NothingChanged => "自上次检查以来没有变化。".into(),
+            Text::FilteredOut => "你的进度有变化，但不在 /notify 设定的通知范围内。".into(),
+            Text::RefreshCooldown(secs) => format!("刚刚检查过，请在 {} 秒后重试。", secs),
+            Text::RefreshRunning => "正在检查中，请稍后再试。".into(),
+            Text::Expired => "你的令牌已过期，轮询已暂停。使用 /signin 提供新的令牌即可恢复轮询，或使用 /signout 停止提醒。".into(),
+            Text::Reminder => "你的令牌仍处于过期状态。使用 /signin 提供新的令牌，或使用 /signout 停止这些提醒。".into(),
+
+            Text::NotifyPrompt(filter) => format!(
+                "你目前接收{}的通知。请选择要接收的通知。",
+                zh_cn_filter(*filter)
+            ),
+            Text::NotifySet(filter) => format!("你将接收{}的通知。", zh_cn_filter(*filter)),
+            Text::FilterButton(NotificationFilter::All) => "全部".into(),
+            Text::FilterButton(NotificationFilter::StepChanges) => "步骤推进".into(),
+            Text::FilterButton(NotificationFilter::Assessments) => "测评与笔试".into(),
+            Text::FilterButton(NotificationFilter::From(step)) => {
+                format!("{}起", Text::Step(*step).zh_cn())
+            }
+
+            Text::WebhookUsage => "请提供 HTTPS 地址，例如 /webhook https://example.com/qazer。".into(),
+            Text::WebhookNotPublic => "Webhook 地址必须解析到公网地址。".into(),
+            Text::WebhookSet { url, secret } => format!(
+                "通知将转发到 {}。每条通知都以密钥 {} 进行 HMAC-SHA256 签名，签名位于 {} 请求头中。",
+                url, secret, SIGNATURE_HEADER
+            ),
+            Text::WebhookOff => "已停止转发通知。".into(),
+
+            Text::EmailUnavailable => "此机器人未配置邮件。".into(),
+            Text::EmailOff => "已停止通过邮件发送通知。".into(),
+            Text::EmailUsage => "请提供邮箱地址，例如 /email me@example.com。".into(),
+            Text::EmailSendFailed(address) => {
+                format!("无法向 {} 发送邮件。请检查地址后重试。", address)
+            }
+            Text::EmailCodeSent(address) => format!(
+                "验证码已发送到 {}。请发送 /email 加上验证码进行确认。",
+                address
+            ),
+            Text::EmailConfirmed(address) => format!("已确认。通知也将发送到 {}。", address),
+            Text::EmailCodeMismatch => "验证码不匹配。请查看最新的邮件后重试。".into(),
+            Text::EmailTooManyCodes(address) => {
+                format!("错误次数过多。请发送 /email {} 获取新的验证码。", address)
+            }
+            Text::EmailNothingPending => "没有等待确认的地址。请先发送 /email 加上你的邮箱地址。".into(),
+            Text::EmailTooManySends(wait) => format!(
+                "最近发送的验证码过多，请在 {} 后重试。",
+                format_duration(*wait)
+            ),
+
+            Text::LetterSubject(summary) => format!("Qazer：{}", summary),
+            Text::LetterSubjectMany(count) => format!("Qazer：{} 条进度更新", count),
+            Text::LetterIntro => "你的申请有新进展：".into(),
+            Text::LetterUrgent => "请尽快处理".into(),
+            Text::CodeSubject => "Qazer 验证码".into(),
+            Text::CodeBody(code, command) => format!(
+                "你的验证码是 {}。向机器人发送 {} 即可在此地址接收通知。",
+                code, command
+            ),
+
+            Text::DigestStep(step) => {
+                format!("每日摘要：你的申请处于{}阶段。", Text::Step(*step).zh_cn())
+            }
+            Text::DigestNoStep => "每日摘要：你的申请目前没有所处的步骤。".into(),
+            Text::DigestUnknown(e) => format!("每日摘要：无法识别你的申请进度（{}）。", e),
+
+            Text::Step(step) => match step {
+                Step::CvDeliverance => "简历投递",
+                Step::Examination => "测评",
+                Step::WrittenTest => "笔试",
+                Step::GroupInterview => "群面",
+                Step::PreliminaryInterview => "初试",
+                Step::SecondaryInterview => "复试",
+                Step::HrInterview => "HR 面试",
+                Step::EmployerAssessment => "录用评估",
+                Step::EmployeeConfirmation => "意向确认",
+                Step::OfferConfirmation => "Offer 确认",
+                Step::SignUp => "签约",
+                Step::Completed => "已完成",
+            }
+            .into(),
+            Text::NoStep => "无".into(),
+            Text::UnknownProgress(e) => format!("无法识别的进度（{}）", e),
+            Text::ProgressUpdate(summary) => format!("进度更新：{}", summary),
+            Text::ProgressUpdates(summaries) => summaries.iter().fold(
+                "自上条消息以来的进度更新：".to_string(),
+                |text, summary| format!("{}\n- {}", text, summary),
+            ),
+
+            Text::InterviewPosition => "面试岗位".into(),
+            Text::SubDirection => "细分方向".into(),
+            Text::NothingToShow => "暂无内容。请查看网页了解更多信息。".into(),
+            Text::CantMakeSense(e) => format!("无法识别申请进度：{}。", e),
+            Text::WrittenTest => "笔试".into(),
+            Text::Assessment => "测评".into(),
+            Text::StageState(status) => match status {
+                1 => "未开始".into(),
+                2 => "进行中".into(),
+                3 => "已完成".into(),
+                other => format!("状态 {}", other),
+            },
+            Text::LastChanged(since) => {
+                let minutes = since.as_secs() / 60;
+                match minutes {
+                    0 => "刚刚有变化。".into(),
+                    1..60 => format!("{} 分钟前有变化。", minutes),
+                    60..1440 => format!("{} 小时前有变化。", minutes / 60),
+                    _ => format!("{} 天前有变化。", minutes / 1440),
+                }
+            }
+            Text::TakeAssessment => "参加测评".into(),
+            Text::OpenJoinQq => "打开 join.qq.com".into(),
+
+            Text::LanguagePrompt(None, lang) => {
+                format!("消息目前使用{}，跟随你的 Telegram 客户端。请选择语言。", lang.name())
+            }
+            Text::LanguagePrompt(Some(lang), _) => {
+                format!("消息目前使用{}。请选择语言。", lang.name())
+            }
+            Text::FollowTelegram => "跟随 Telegram".into(),
+            Text::LanguageSet(lang) => format!("消息现在使用{}。", lang.name()),
+            Text::LanguageFollowing(lang) => {
+                format!("消息现在跟随你的 Telegram 客户端，当前为{}。", lang.name())
+            }
+        }
+    }
+}
+
+/// Rounds down to whole minutes, so countdowns don't pretend to be exact.
+fn whole_minutes(duration: Duration) -> Duration {
+    Duration::from_secs(duration.as_secs() / 60 * 60)
+}
+
+fn en_filter(filter: NotificationFilter) -> String {
+    match filter {
+        NotificationFilter::All => "every change".into(),
+        NotificationFilter::StepChanges => "step advances only".into(),
+        NotificationFilter::From(step) => {
+            format!("changes from {} onward", Text::Step(step).en())
+        }
+        NotificationFilter::Assessments => "assessments and written tests only".into(),
+    }
+}
+
+fn zh_cn_filter(filter: NotificationFilter) -> String {
+    match filter {
+        NotificationFilter::All => "所有变化".into(),
+        NotificationFilter::StepChanges => "仅步骤推进".into(),
+        NotificationFilter::From(step) => format!("{}及之后", Text::Step(step).zh_cn()),
+        NotificationFilter::Assessments => "仅测评与笔试".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_catalog_by_tag() {
+        assert_eq!(Lang::from_tag("zh-hans"), Lang::ZhCn);
+        assert_eq!(Lang::from_tag("zh_CN"), Lang::ZhCn);
+        assert_eq!(Lang::from_tag("en-US"), Lang::En);
+        assert_eq!(Lang::from_tag("fr"), Lang::En);
+        for lang in Lang::ALL {
+            assert_eq!(Lang::from_tag(lang.tag()), lang);
+        }
+    }
+
+    #[test]
+    fn prefers_chosen_language_over_client() {
+        let mut settings = UserSettings {
+            client_language: Some("zh-hans".into()),
+            ..Default::default()
+        };
+        assert_eq!(Lang::of(&settings), Lang::ZhCn);
+        settings.language = Some("en".into());
+        assert_eq!(Lang::of(&settings), Lang::En);
+        assert_eq!(Lang::of(&UserSettings::default()), Lang::En);
+    }
+
+    #[test]
+    fn help_covers_every_command() {
+        let commands: Vec<_> = Command::bot_commands()
+            .into_iter()
+            .map(|c| format!("/{}", c.command.trim_start_matches('/')))
+            .collect();
+        // the Chinese help is written out by hand, apart from the descriptions in
+        // cmd.rs the English one is generated from
+        for lang in Lang::ALL {
+            let help = Text::Help.localize(lang);
+            let listed: Vec<_> = help
+                .lines()
+                .filter_map(|line| line.split_once(' ').map(|(command, _)| command))
+                .collect();
+            assert_eq!(listed, commands, "{:?}", lang);
+        }
+    }
+
+    #[test]
+    fn describes_polling() {
+        let minute = Duration::from_secs(60);
+        let status = |interval, adaptive, next| {
+            Text::IntervalStatus {
+                interval,
+                adaptive,
+                next,
+                min: minute,
+                max: minute * 60 * 24,
+            }
+            .localize(Lang::En)
+        };
+        assert_eq!(
+            status(Some(minute * 45), None, Some(minute * 12 + minute / 2)),
+            "Polling every 45m. Next poll in 12m.\n\
+             Choose an interval, or send one between 1m and 1d like /interval 45m."
+        );
+        let adaptive = AdaptivePolling {
+            min: minute * 5,
+            max: minute * 60 * 12,
+        };
+        assert!(status(Some(minute * 5), Some(adaptive), Some(minute / 2))
+            .starts_with("Polling adapts between 5m and 12h. Next poll in under a minute.\n"));
+        assert!(status(None, None, None).starts_with("Polling is off.\n"));
+    }
+
+    #[test]
+    fn describes_elapsed_time() {
+        let minute = Duration::from_secs(60);
+        let en = |since| Text::LastChanged(since).localize(Lang::En);
+        assert_eq!(en(minute / 2), "Last changed just now.");
+        assert_eq!(en(minute), "Last changed 1 minute ago.");
+        assert_eq!(en(minute * 59), "Last changed 59 minutes ago.");
+        assert_eq!(en(minute * 60), "Last changed 1 hour ago.");
+        assert_eq!(en(minute * 60 * 50), "Last changed 2 days ago.");
+        assert_eq!(
+            Text::LastChanged(minute * 90).localize(Lang::ZhCn),
+            "1 小时前有变化。"
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use crate::bot::clients::ClientCollection;
+use crate::bot::i18n::{Lang, Text};
+use crate::clock::SharedClock;
+use crate::repo::model::{AccountIndex, Repository};
+use crate::repo::schema;
+use crate::repo::settings::{NotificationFilter, UserSettings};
+use crate::tencent::error::Error as ClientError;
+use crate::tencent::model::ApplicationProgress;
+use redb::Database;
+use std::collections::HashSet;
+use std::fmt::{Debug, Display, Formatter};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use teloxide::prelude::{Requester, UserId};
+use teloxide::Bot;
+use tokio::sync::Mutex;
+
+/// Everything the monitoring loop runs on a schedule.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Job {
+    Poll(AccountIndex),
+    /// Reminds the owner of an expired token to sign in again, while polling the
+    /// account is paused.
+    Reauth(AccountIndex),
+    /// Daily summary of where each polled application stands.
+    Digest,
+    /// Checks the tokens of accounts not being polled, which would otherwise
+    /// expire unnoticed.
+    TokenHealth,
+    /// Retries notifications that couldn't be delivered.
+    Outbox,
+    Backup,
+    /// Drops records left behind by accounts that no longer have a token, and due
+    /// times of jobs no longer scheduled.
+    Cleanup,
+}
+
+/// The name a job's due time is persisted under.
+impl Display for Job {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Job::Poll(account) => write!(f, "poll:{}", account),
+            Job::Reauth(account) => write!(f, "reauth:{}", account),
+            Job::Digest => write!(f, "digest"),
+            Job::TokenHealth => write!(f, "token-health"),
+            Job::Outbox => write!(f, "outbox"),
+            Job::Backup => write!(f, "backup"),
+            Job::Cleanup => write!(f, "cleanup"),
+        }
+    }
+}
+
+impl FromStr for Job {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "digest" => Ok(Job::Digest),
+            "token-health" => Ok(Job::TokenHealth),
+            "outbox" => Ok(Job::Outbox),
+            "backup" => Ok(Job::Backup),
+            "cleanup" => Ok(Job::Cleanup),
+            _ => match s.split_once(':') {
+                Some(("poll", account)) => account.parse().map(Job::Poll).map_err(|_| ()),
+                Some(("reauth", account)) => account.parse().map(Job::Reauth).map_err(|_| ()),
+                _ => Err(()),
+            },
+        }
+    }
+}
+
+/// Time before the first reminder to sign in again.
+const FIRST_REMINDER: Duration = Duration::from_secs(24 * 60 * 60);
+/// Longest time between two reminders.
+const MAX_REMINDER_GAP: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Time until the next reminder after `sent` of them, which doubles each time up
+/// to a week.
+pub fn reminder_gap(sent: u32) -> Duration {
+    FIRST_REMINDER
+        .saturating_mul(1 << sent.min(8))
+        .min(MAX_REMINDER_GAP)
+}
+
+/// Where [`Job::Backup`] copies the database to.
+pub struct Backup {
+    pub db: Arc<Database>,
+    pub path: PathBuf,
+}
+
+impl Backup {
+    pub fn run(&self) {
+        match schema::backup(&self.db, &self.path) {
+            Ok(_) => println!("Backed up database to {}", self.path.display()),
+            Err(e) => eprintln!("Error while backing up database: {}", e),
+        }
+    }
+}
+
+/// Tells everyone being polled where their application stands, as far as their
+/// filter lets a digest through. Accounts signed out, paused or `expired` get
+/// none, and a digest falling into the quiet hours goes out once they end.
+pub async fn send_digests<AP, S>(
+    bot: &Arc<Bot>,
+    clients: &Mutex<ClientCollection>,
+    settings: &Mutex<S>,
+    cache: &Mutex<AP>,
+    expired: &HashSet<AccountIndex>,
+    clock: &SharedClock,
+) where
+    AP: Repository<ApplicationProgress>,
+    <AP as Repository<ApplicationProgress>>::Err: Debug,
+    S: Repository<UserSettings>,
+    <S as Repository<UserSettings>>::Err: Debug,
+{
+    let accounts = clients.lock().await.accounts();
+    for acc in accounts {
+        if expired.contains(&acc) {
+            continue;
+        }
+        let settings = match settings.lock().await.get(acc) {
+            Ok(Some(settings)) if settings.interval.is_some() => settings,
+            Ok(_) => continue,
+            Err(e) => {
+                eprintln!("Error while reading settings, user id = {}: {:?}", acc, e);
+                continue;
+            }
+        };
+        let progress = match cache.lock().await.get(acc) {
+            Ok(Some(progress)) => progress,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!(
+                    "Error while reading cached progress, user id = {}: {:?}",
+                    acc, e
+                );
+                continue;
+            }
+        };
+        if !wants_digest(settings.filter, &progress) {
+            continue;
+        }
+        let text = digest_text(&progress).localize(Lang::of(&settings));
+        let now = clock.system_now();
+        match settings.quiet_hours.filter(|quiet| quiet.contains(now)) {
+            Some(quiet) => {
+                let end = clock.sleep_until(clock.now() + quiet.until_end(now));
+                let bot = bot.clone();
+                tokio::spawn(async move {
+                    end.await;
+                    send_digest(&bot, acc, text).await;
+                });
+            }
+            None => send_digest(bot, acc, text).await,
+        }
+    }
+}
+
+async fn send_digest(bot: &Bot, acc: AccountIndex, text: String) {
+    if let Err(e) = bot.send_message(UserId(acc), text).await {
+        eprintln!("Error while sending digest: {}, user id = {}", e, acc)
+    }
+}
+
+/// Whether the filter lets a digest through. A digest isn't a change, so those
+/// who only want to hear about changes or assessments don't get one.
+fn wants_digest(filter: NotificationFilter, progress: &ApplicationProgress) -> bool {
+    match filter {
+        NotificationFilter::All => true,
+        NotificationFilter::From(from) => progress
+            .get_current_step()
+            .ok()
+            .flatten()
+            .is_some_and(|step| step >= from),
+        NotificationFilter::StepChanges | NotificationFilter::Assessments => false,
+    }
+}
+
+fn digest_text(progress: &ApplicationProgress) -> Text {
+    match progress.get_current_step() {
+        Ok(Some(step)) => Text::DigestStep(step),
+        Ok(None) => Text::DigestNoStep,
+        Err(e) => Text::DigestUnknown(e.to_string()),
+    }
+}
+
+/// Finds the expired tokens that polling wouldn't notice, because it is turned off
+/// for them.
+pub async fn check_tokens<S>(
+    clients: &Mutex<ClientCollection>,
+    settings: &Mutex<S>,
+) -> Vec<AccountIndex>
+where
+    S: Repository<UserSettings>,
+    <S as Repository<UserSettings>>::Err: Debug,
+{
+    let mut expired = Vec::new();
+    let accounts = clients.lock().await.accounts();
+    for acc in accounts {
+        let polled = matches!(
+            settings.lock().await.get(acc),
+            Ok(Some(UserSettings {
+                interval: Some(_),
+                ..
+            }))
+        );
+        if polled {
+            continue;
+        }
+        let Some(client) = clients.lock().await.get(acc).await else {
+            continue;
+        };
+        let result = client.lock().await.get_application_progress().await;
+        match result {
+            Ok(_) => {}
+            Err(ClientError::TokenExpired) => expired.push(acc),
+            Err(e) => eprintln!("Error while checking token: {}, user id = {}", e, acc),
+        }
+    }
+    expired
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{reminder_gap, wants_digest, Job};
+    use crate::repo::settings::NotificationFilter;
+    use crate::tencent::model::ApplicationProgress;
+    use crate::tencent::progress::Step;
+    use std::time::Duration;
+
+    #[test]
+    fn job_names_round_trip() {
+        for job in [
+            Job::Poll(42),
+            Job::Reauth(42),
+            Job::Digest,
+            Job::TokenHealth,
+            Job::Outbox,
+            Job::Backup,
+            Job::Cleanup,
+        ] {
+            assert_eq!(job.to_string().parse(), Ok(job));
+        }
+        assert_eq!("poll:x".parse::<Job>(), Err(()));
+    }
+
+    #[test]
+    fn reminders_grow_apart() {
+        let day = Duration::from_secs(24 * 60 * 60);
+        let gaps: Vec<_> = (0..5).map(reminder_gap).collect();
+        assert_eq!(gaps, [day, day * 2, day * 4, day * 7, day * 7]);
+        assert_eq!(reminder_gap(u32::MAX), day * 7);
+    }
+
+    #[test]
+    fn digests_follow_the_filter() {
+        let progress: ApplicationProgress = serde_json::from_str(
+            r#"{
+            "resumeId":1,
+            "currentStatus":{"status":1,"applyProcessType":1},
+            "assessmentInfo":{"status":2,"testAddress":"","mobileTail":""},
+            "positionInfo":{"applyPositionTxt":"Engineer"},
+            "resumeStatus":{"status":3,"isPublic":1},
+            "writtenTestInfo":{"status":0,"itemList":[]},
+            "campusRecruitOne":{"id":1,"itemList":[],"recruitType":1,"typeName":""},
+            "campusRecruitTwo":{"itemList":[],"bgid":1}}"#,
+        )
+        .unwrap();
+        assert!(wants_digest(NotificationFilter::All, &progress));
+        assert!(wants_digest(
+            NotificationFilter::From(Step::Examination),
+            &progress
+        ));
+        assert!(!wants_digest(
+            NotificationFilter::From(Step::HrInterview),
+            &progress
+        ));
+        assert!(!wants_digest(NotificationFilter::StepChanges, &progress));
+        assert!(!wants_digest(NotificationFilter::Assessments, &progress));
+    }
+}
@@ -1,92 +1,408 @@
+use crate::bot::card;
 use crate::bot::change::StatusChange;
 use crate::bot::clients::ClientCollection;
-use crate::repo::model::{AccountIndex, Repository};
+use crate::bot::cmd::Callback;
+use crate::bot::dialogue::{SignInDialogue, State};
+use crate::bot::i18n::{Lang, Text};
+use crate::bot::jobs::{self, Backup, Job};
+use crate::bot::notify::email::{Letter, Mailer};
+use crate::bot::notify::{webhook, Notifier, Telegram};
+use crate::bot::pace::{self, Observation, Pace};
+use crate::clock::{SharedClock, SystemClock};
+use crate::repo::ledger::{Ledger, Notification};
+use crate::repo::model::{
+    AccountIndex, Repository, ScheduleRepository, TransactionalRepository, UnitOfWork,
+};
+use crate::repo::settings::{
+    AdaptivePolling, EmailTarget, NotificationFilter, QuietHours, UserSettings, WebhookTarget,
+};
 use crate::tencent::model::ApplicationProgress;
+use crate::tencent::progress::Step;
 use crate::tencent::ClientResult;
-use crate::watch::Watcher;
-use std::fmt::Debug;
+use crate::watch::recurrence::{format_duration, parse_duration, Recurrence};
+use crate::watch::{Jitter, ScheduleChange, Watcher};
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+use rand::{Rng, RngCore};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Display, Formatter};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
+use teloxide::dispatching::dialogue::ErasedStorage;
 use teloxide::payloads::SendMessageSetters;
 use teloxide::prelude::{ChatId, Message, Requester, ResponseResult, UserId};
-use teloxide::types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup};
+use teloxide::types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup, ParseMode, User};
 use teloxide::Bot;
 use tokio::select;
 use tokio::sync::mpsc::{Receiver, Sender};
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Semaphore};
+use tokio::task::{self, JoinSet};
+use tokio::time::Instant;
 
 type TClient = crate::tencent::Client;
 
-pub struct Basic<Tokens, APs, Intervals>
+pub struct Basic<Tokens, APs, Settings, Ledgers, Schedule>
 where
     Tokens: Repository<String>,
     APs: Repository<ApplicationProgress>,
+    Ledgers: Repository<Ledger>,
+    Schedule: ScheduleRepository,
 {
     tokens: Tokens,
     cache: Arc<Mutex<APs>>,
-    intervals: Arc<Mutex<Intervals>>,
+    settings: Arc<Mutex<Settings>>,
+    ledger: Arc<Mutex<Ledgers>>,
+    /// Read for when accounts are polled next. Only [`Watch`] writes to it.
+    schedule: Schedule,
     clients: Arc<Mutex<ClientCollection>>,
-    ic_tx: Sender<(AccountIndex, Duration)>,
+    ic_tx: Sender<ScheduleChange<Job>>,
+    /// Dialogue state of each chat, dropped along with the account by /forgetme.
+    dialogues: Arc<ErasedStorage<State>>,
+    /// Sends confirmation codes for /email, or `None` if email isn't set up.
+    mailer: Option<Arc<Mailer>>,
+    limits: IntervalLimits,
+    clock: SharedClock,
 }
 
-impl<R, T, I> Basic<R, T, I>
+/// Wrong confirmation codes accepted before the pending address is dropped.
+const MAX_CODE_ATTEMPTS: u32 = 5;
+/// Confirmation codes mailed for an account within a day, so /email can't be used
+/// to flood someone's inbox.
+const MAX_CODE_SENDS: usize = 3;
+const CODE_SENDS_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+/// Intervals offered on the keyboard of /interval, in minutes.
+const INTERVAL_OPTIONS: [u32; 9] = [1, 3, 5, 10, 30, 60, 120, 360, 1440];
+
+/// Polling intervals users may choose, so no one polls too eagerly.
+#[derive(Clone, Debug)]
+pub struct IntervalLimits {
+    pub min: Duration,
+    pub max: Duration,
+    /// Shortest interval of particular accounts, taking the place of `min`. Caps
+    /// may only narrow the range, so they lie within `min` and `max`.
+    pub caps: HashMap<AccountIndex, Duration>,
+}
+
+/// Why interval limits were turned down.
+#[derive(Debug, PartialEq)]
+pub enum LimitsError {
+    /// The minimum is above the maximum.
+    Inverted(Duration, Duration),
+    /// The cap of an account lies outside the minimum and maximum.
+    CapOutOfRange(AccountIndex, Duration),
+}
+
+impl Display for LimitsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitsError::Inverted(min, max) => write!(
+                f,
+                "minimum {} is above maximum {}",
+                format_duration(*min),
+                format_duration(*max)
+            ),
+            LimitsError::CapOutOfRange(account, cap) => write!(
+                f,
+                "cap {} of account {} is outside the minimum and maximum",
+                format_duration(*cap),
+                account
+            ),
+        }
+    }
+}
+
+impl Default for IntervalLimits {
+    fn default() -> Self {
+        Self {
+            min: Duration::from_secs(60),
+            max: Duration::from_secs(24 * 60 * 60),
+            caps: HashMap::new(),
+        }
+    }
+}
+
+impl IntervalLimits {
+    pub fn validate(&self) -> Result<(), LimitsError> {
+        if self.min > self.max {
+            return Err(LimitsError::Inverted(self.min, self.max));
+        }
+        match self
+            .caps
+            .iter()
+            .find(|(_, cap)| !(self.min..=self.max).contains(*cap))
+        {
+            Some((&account, &cap)) => Err(LimitsError::CapOutOfRange(account, cap)),
+            None => Ok(()),
+        }
+    }
+
+    /// Shortest and longest interval the account may choose.
+    fn bounds(&self, account: AccountIndex) -> (Duration, Duration) {
+        let min = self.caps.get(&account).copied().unwrap_or(self.min);
+        (min, self.max)
+    }
+
+    fn check(&self, account: AccountIndex, interval: Duration) -> Result<(), Text> {
+        let (min, max) = self.bounds(account);
+        if (min..=max).contains(&interval) {
+            Ok(())
+        } else {
+            Err(Text::IntervalOutOfRange(min, max))
+        }
+    }
+}
+
+/// Failure of an operation spanning several repositories, only kept for logging.
+struct StoreError(String);
+
+impl StoreError {
+    fn of(err: impl Debug) -> Self {
+        Self(format!("{:?}", err))
+    }
+}
+
+impl Display for StoreError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl<R, T, S, L, D> Basic<R, T, S, L, D>
 where
-    R: Repository<String>,
-    T: Repository<ApplicationProgress>,
-    I: Repository<Duration>,
+    R: TransactionalRepository<String>,
+    T: TransactionalRepository<ApplicationProgress, Unit = R::Unit>,
+    S: TransactionalRepository<UserSettings, Unit = R::Unit>,
+    L: TransactionalRepository<Ledger, Unit = R::Unit>,
     <R as Repository<String>>::Err: Debug,
     <T as Repository<ApplicationProgress>>::Err: Debug,
-    <I as Repository<Duration>>::Err: Debug,
+    <S as Repository<UserSettings>>::Err: Debug,
+    <L as Repository<Ledger>>::Err: Debug,
+    <R::Unit as UnitOfWork>::Err: Debug,
+    D: ScheduleRepository,
+    <D as ScheduleRepository>::Err: Debug,
 {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         tokens: R,
         cache: Arc<Mutex<T>>,
-        intervals: Arc<Mutex<I>>,
+        settings: Arc<Mutex<S>>,
+        ledger: Arc<Mutex<L>>,
+        schedule: D,
         clients: Arc<Mutex<ClientCollection>>,
-        interval_change_tx: Sender<(AccountIndex, Duration)>,
-    ) -> Basic<R, T, I> {
+        interval_change_tx: Sender<ScheduleChange<Job>>,
+        dialogues: Arc<ErasedStorage<State>>,
+    ) -> Basic<R, T, S, L, D> {
         Self {
             tokens,
             cache,
-            intervals,
+            settings,
+            ledger,
+            schedule,
             clients,
             ic_tx: interval_change_tx,
+            dialogues,
+            mailer: None,
+            limits: IntervalLimits::default(),
+            clock: SystemClock::shared(),
+        }
+    }
+
+    pub fn with_mailer(mut self, mailer: Arc<Mailer>) -> Self {
+        self.mailer = Some(mailer);
+        self
+    }
+
+    pub fn with_interval_limits(mut self, limits: IntervalLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Replaces the clock changes are stamped with and due times are counted from.
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// When the progress last changed, as far as polling knows. `None` if `value`
+    /// is news to it, which is left for the next poll to record and notify about.
+    async fn last_change(
+        &self,
+        account: AccountIndex,
+        value: &ApplicationProgress,
+    ) -> Result<Option<SystemTime>, StoreError> {
+        if self
+            .cache
+            .lock()
+            .await
+            .get(account)
+            .map_err(StoreError::of)?
+            .as_ref()
+            != Some(value)
+        {
+            return Ok(None);
         }
+        let ledger = self
+            .ledger
+            .lock()
+            .await
+            .get(account)
+            .map_err(StoreError::of)?;
+        Ok(ledger.and_then(|entries| entries.changed()))
+    }
+
+    async fn notify_schedule(&self, change: ScheduleChange<Job>) {
+        self.ic_tx
+            .send(change)
+            .await
+            .unwrap_or_else(|_| panic!("Failed to notify schedule change: {:?}", change));
+    }
+
+    async fn update_settings(
+        &self,
+        account: AccountIndex,
+        update: impl FnOnce(&mut UserSettings),
+    ) -> Result<(), S::Err> {
+        let mut repo = self.settings.lock().await;
+        let mut settings = repo.get(account)?.unwrap_or_default();
+        update(&mut settings);
+        repo.put(account, settings)
     }
 
-    async fn update_status(&self, account: AccountIndex, value: &ApplicationProgress) {
-        self.cache
+    /// Language to address the user in, following the client where there's no
+    /// preference. The client's language is only stored at sign-in and through
+    /// /language, for what's sent on the bot's own initiative.
+    async fn lang_of(&self, user: &User) -> Lang {
+        let acc = user.id.0;
+        let mut settings = self
+            .settings
             .lock()
             .await
-            .put(account, value.clone())
-            .expect("Failed to update database")
+            .get(acc)
+            .unwrap_or_else(|e| {
+                eprintln!("Error while reading settings, user id = {}: {:?}", acc, e);
+                None
+            })
+            .unwrap_or_default();
+        if user.language_code.is_some() {
+            settings.client_language = user.language_code.clone();
+        }
+        Lang::of(&settings)
+    }
+
+    /// Stores the token along with the progress it was validated against, so an
+    /// account is either fully registered or not at all.
+    async fn register(
+        &self,
+        account: AccountIndex,
+        token: String,
+        progress: &ApplicationProgress,
+    ) -> Result<(), StoreError> {
+        let cache = self.cache.lock().await;
+        let unit = self.tokens.begin().map_err(StoreError::of)?;
+        self.tokens
+            .put_in(&unit, account, token)
+            .map_err(StoreError::of)?;
+        cache
+            .put_in(&unit, account, progress.clone())
+            .map_err(StoreError::of)?;
+        unit.commit().map_err(StoreError::of)
+    }
+
+    /// Drops the token, the progress cache and the notifications together and
+    /// turns polling off, returning whether a token was stored. Other preferences
+    /// are kept around for the next sign-in.
+    async fn unregister(&self, account: AccountIndex) -> Result<bool, StoreError> {
+        let cache = self.cache.lock().await;
+        let ledger = self.ledger.lock().await;
+        let settings = self.settings.lock().await;
+        let unit = self.tokens.begin().map_err(StoreError::of)?;
+        let token = self
+            .tokens
+            .revoke_in(&unit, account)
+            .map_err(StoreError::of)?;
+        cache.revoke_in(&unit, account).map_err(StoreError::of)?;
+        ledger.revoke_in(&unit, account).map_err(StoreError::of)?;
+        if let Some(mut prefs) = settings.get(account).map_err(StoreError::of)? {
+            prefs.interval = None;
+            settings
+                .put_in(&unit, account, prefs)
+                .map_err(StoreError::of)?;
+        }
+        unit.commit().map_err(StoreError::of)?;
+        Ok(token.is_some())
+    }
+
+    /// Removes the token, progress, notifications and settings of the account in
+    /// one unit of work, then the dialogue state of the account's chat. Its due
+    /// times go once [`Watch`] handles the removal.
+    async fn erase_account(&self, account: AccountIndex) -> Result<(), StoreError> {
+        let cache = self.cache.lock().await;
+        let ledger = self.ledger.lock().await;
+        let settings = self.settings.lock().await;
+        let unit = self.tokens.begin().map_err(StoreError::of)?;
+        self.tokens
+            .revoke_in(&unit, account)
+            .map_err(StoreError::of)?;
+        cache.revoke_in(&unit, account).map_err(StoreError::of)?;
+        ledger.revoke_in(&unit, account).map_err(StoreError::of)?;
+        settings.revoke_in(&unit, account).map_err(StoreError::of)?;
+        unit.commit().map_err(StoreError::of)?;
+        // the account's own chat with the bot shares its id
+        self.dialogues
+            .clone()
+            .remove_dialogue(ChatId(account as i64))
+            .await
+            .map_err(StoreError::of)
+    }
+
+    pub async fn help(&mut self, bot: &Bot, msg: Message) -> ResponseResult<()> {
+        let lang = match msg.from {
+            Some(ref user) => self.lang_of(user).await,
+            None => Lang::default(),
+        };
+        bot.send_message(msg.chat.id, Text::Help.localize(lang))
+            .await?;
+        Ok(())
     }
 
     pub async fn get(&mut self, bot: &Bot, msg: Message) -> ResponseResult<()> {
         match msg.from {
             None => {
-                bot.send_message(msg.chat.id, "No user info is bound to this context.")
-                    .await?;
+                send_no_user(msg.chat.id, bot).await?;
             }
             Some(user) => {
                 let acc_idx = user.id.0;
-                match self.clients.lock().await.get(acc_idx).await {
+                let lang = self.lang_of(&user).await;
+                let client = self.clients.lock().await.get(acc_idx).await;
+                match client {
                     None => {
-                        bot.send_message(msg.chat.id, "No token associated with current context. Use the /signin command to get started.").await?;
+                        bot.send_message(msg.chat.id, Text::NoToken.localize(lang))
+                            .await?;
                     }
                     Some(client) => {
                         match client.lock().await.get_application_progress().await {
                             Ok(ap) => {
-                                self.update_status(acc_idx, &ap).await;
-                                match ap.get_current_step() {
-                                    Ok(Some(step)) => bot.send_message(msg.chat.id, format!("Current progress is {:?}.", step)).await?,
-                                    Ok(None) => bot.send_message(msg.chat.id, "Current progress is empty or doesn't make sense. Check the web page for more info.").await?,
-                                    Err(err) => bot.send_message(msg.chat.id, format!("Fetch succeeded but can't make sense of the result because {}", err)).await?
-                                }
+                                let changed =
+                                    self.last_change(acc_idx, &ap).await.unwrap_or_else(|e| {
+                                        eprintln!(
+                                            "Error while reading progress, user id = {}: {}",
+                                            acc_idx, e
+                                        );
+                                        None
+                                    });
+                                let since = changed
+                                    .and_then(|at| self.clock.system_now().duration_since(at).ok());
+                                let (card, keyboard) = card::status_card(&ap, since, lang);
+                                bot.send_message(msg.chat.id, card)
+                                    .parse_mode(ParseMode::Html)
+                                    .reply_markup(keyboard)
+                                    .await?
                             }
                             Err(e) => {
-                                bot.send_message(msg.chat.id, format!("Fetch failed because {}", e))
-                                    .await?
+                                bot.send_message(
+                                    msg.chat.id,
+                                    Text::FetchFailed(e.to_string()).localize(lang),
+                                )
+                                .await?
                             }
                         };
                     }
@@ -96,130 +412,586 @@ where
         Ok(())
     }
 
-    pub async fn signin(&mut self, bot: &Bot, msg: Message, token: String) -> ResponseResult<()> {
+    /// Signs in with the token given along, or asks for it in the next message.
+    pub async fn signin(
+        &mut self,
+        bot: &Bot,
+        msg: Message,
+        token: String,
+        dialogue: SignInDialogue,
+    ) -> ResponseResult<()> {
+        let Some(user) = msg.from.clone() else {
+            send_no_user(msg.chat.id, bot).await?;
+            return Ok(());
+        };
+        let lang = self.lang_of(&user).await;
+        let token = token.trim();
         if token.is_empty() {
-            bot.send_message(msg.chat.id, "Empty token. This operation has no effect.")
+            if let Err(e) = dialogue.update(State::ReceiveToken).await {
+                eprintln!("Error while starting sign-in, user id = {}: {}", user.id, e);
+                bot.send_message(msg.chat.id, Text::DatabaseError(user.id.0).localize(lang))
+                    .await?;
+                return Ok(());
+            }
+            bot.send_message(msg.chat.id, Text::SignInPrompt.localize(lang))
+                .await?;
+            return Ok(());
+        }
+        delete_token_message(bot, &msg, lang).await?;
+        self.sign_in_with(bot, msg.chat.id, &user, token.to_string(), lang)
+            .await
+    }
+
+    /// Takes the message following /signin as the token, deleting it right away.
+    pub async fn receive_token(
+        &mut self,
+        bot: &Bot,
+        msg: Message,
+        dialogue: SignInDialogue,
+    ) -> ResponseResult<()> {
+        let Some(user) = msg.from.clone() else {
+            send_no_user(msg.chat.id, bot).await?;
+            return Ok(());
+        };
+        let lang = self.lang_of(&user).await;
+        let Some(token) = msg.text().map(str::trim).filter(|t| !t.is_empty()) else {
+            bot.send_message(msg.chat.id, Text::TokenExpected.localize(lang))
                 .await?;
             return Ok(());
+        };
+        let token = token.to_string();
+        delete_token_message(bot, &msg, lang).await?;
+        if let Err(e) = dialogue.exit().await {
+            eprintln!("Error while ending sign-in, user id = {}: {}", user.id, e);
         }
+        self.sign_in_with(bot, msg.chat.id, &user, token, lang)
+            .await
+    }
+
+    async fn sign_in_with(
+        &mut self,
+        bot: &Bot,
+        chat_id: ChatId,
+        user: &User,
+        token: String,
+        lang: Lang,
+    ) -> ResponseResult<()> {
         let new_client = TClient::with_token(&token);
         match new_client.get_application_progress().await {
-            Ok(ap) => match msg.from {
-                None => {
-                    bot.send_message(msg.chat.id, "No user info bound to this context.")
-                        .await?;
+            Ok(ap) => {
+                let acc_idx = user.id.0;
+                // held across registering, so cleanup never sees the progress
+                // without its client
+                let mut clients = self.clients.lock().await;
+                let registered = self.register(acc_idx, token, &ap).await;
+                if registered.is_ok() {
+                    clients.insert(acc_idx, new_client).await;
                 }
-                Some(user) => {
-                    let acc_idx = user.id.0;
-                    self.update_status(acc_idx, &ap).await;
-                    self.clients.lock().await.insert(acc_idx, new_client).await;
-                    match self.tokens.put(acc_idx, token) {
-                        Ok(_) => {
-                            bot.send_message(msg.chat.id, "Token has been updated.")
-                                .await?;
-                            bot.edit_message_text(msg.chat.id, msg.id, "/signin")
-                                .await?;
-                        }
-                        Err(e) => {
+                drop(clients);
+                match registered {
+                    Ok(_) => {
+                        // kept for the notifications to come
+                        let client_language = user.language_code.clone();
+                        if let Err(e) = self
+                            .update_settings(acc_idx, |s| {
+                                s.client_language = client_language.or(s.client_language.take())
+                            })
+                            .await
+                        {
                             eprintln!(
-                                "Error while inserting token, user id = {}. {:?}",
+                                "Error while updating settings, user id = {}: {:?}",
                                 acc_idx, e
                             );
-                            bot.send_message(
-                                msg.chat.id,
-                                format!(
-                                    "Error updating database. {}",
-                                    get_contact_admin_text(acc_idx)
-                                ),
-                            )
-                            .await?;
                         }
+                        self.notify_schedule(ScheduleChange::Resume(Job::Poll(acc_idx)))
+                            .await;
+                        bot.send_message(chat_id, Text::TokenUpdated.localize(lang))
+                            .await?;
+                    }
+                    Err(e) => {
+                        eprintln!("Error while inserting token, user id = {}. {}", acc_idx, e);
+                        bot.send_message(chat_id, Text::DatabaseError(acc_idx).localize(lang))
+                            .await?;
                     }
                 }
-            },
+            }
             Err(e) => {
-                bot.send_message(msg.chat.id, format!("Invalid token: {}", e))
+                bot.send_message(chat_id, Text::InvalidToken(e.to_string()).localize(lang))
                     .await?;
             }
         }
         Ok(())
     }
 
-    pub async fn interval(&mut self, bot: &Bot, msg: Message) -> ResponseResult<()> {
-        match msg.from {
+    pub async fn interval(
+        &mut self,
+        bot: &Bot,
+        msg: Message,
+        interval: String,
+    ) -> ResponseResult<()> {
+        let Some(user) = msg.from else {
+            send_no_user(msg.chat.id, bot).await?;
+            return Ok(());
+        };
+        let acc = user.id.0;
+        let lang = self.lang_of(&user).await;
+        let arg = interval.trim();
+        if arg.is_empty() {
+            let status = match self.interval_status(acc).await {
+                Ok(status) => status,
+                Err(e) => {
+                    eprintln!("Error while reading settings, user id = {}: {}", acc, e);
+                    Text::DatabaseReadError(acc)
+                }
+            };
+            bot.send_message(msg.chat.id, status.localize(lang))
+                .reply_markup(make_interval_keyboard(lang, self.limits.bounds(acc)))
+                .await?;
+            return Ok(());
+        }
+        let interval = if arg.eq_ignore_ascii_case("off") {
+            None
+        } else {
+            match parse_duration(arg)
+                .ok_or(Text::IntervalUsage)
+                .and_then(|interval| {
+                    self.limits.check(acc, interval)?;
+                    Ok(interval)
+                }) {
+                Ok(interval) => Some(interval),
+                Err(reason) => {
+                    bot.send_message(msg.chat.id, reason.localize(lang)).await?;
+                    return Ok(());
+                }
+            }
+        };
+        let reply = self.set_interval(acc, interval).await;
+        bot.send_message(msg.chat.id, reply.localize(lang)).await?;
+        Ok(())
+    }
+
+    /// Describes how the account is polled, along with what it may choose.
+    async fn interval_status(&self, account: AccountIndex) -> Result<Text, StoreError> {
+        let settings = self
+            .settings
+            .lock()
+            .await
+            .get(account)
+            .map_err(StoreError::of)?
+            .unwrap_or_default();
+        let due = self
+            .schedule
+            .due(&Job::Poll(account).to_string())
+            .map_err(StoreError::of)?;
+        let (min, max) = self.limits.bounds(account);
+        Ok(Text::IntervalStatus {
+            interval: settings.interval,
+            adaptive: settings.adaptive,
+            next: settings.interval.and(due).map(|due| {
+                due.duration_since(self.clock.system_now())
+                    .unwrap_or_default()
+            }),
+            min,
+            max,
+        })
+    }
+
+    /// Polls the account at a fixed interval, or stops polling it on `None`.
+    async fn set_interval(&self, account: AccountIndex, interval: Option<Duration>) -> Text {
+        let update_result = self
+            .update_settings(account, |s| {
+                s.interval = interval;
+                s.adaptive = None;
+            })
+            .await;
+        if let Err(e) = update_result {
+            eprintln!(
+                "Error while updating interval database, user id = {}: {:?}",
+                account, e
+            );
+            return Text::DatabaseError(account);
+        }
+        match interval {
+            Some(interval) => {
+                self.notify_schedule(ScheduleChange::Reschedule(Job::Poll(account), interval))
+                    .await;
+                Text::IntervalSet(interval)
+            }
             None => {
-                send_no_user(msg.chat.id, bot).await?;
+                self.notify_schedule(ScheduleChange::Remove(Job::Poll(account)))
+                    .await;
+                Text::PollingDisabled
             }
-            Some(_) => {
-                bot.send_message(msg.chat.id, "Choose one of the following intervals.")
-                    .reply_markup(make_interval_keyboard())
+        }
+    }
+
+    pub async fn notify(&mut self, bot: &Bot, msg: Message) -> ResponseResult<()> {
+        let Some(user) = msg.from else {
+            send_no_user(msg.chat.id, bot).await?;
+            return Ok(());
+        };
+        let acc = user.id.0;
+        let lang = self.lang_of(&user).await;
+        let filter = match self.settings.lock().await.get(acc) {
+            Ok(settings) => settings.unwrap_or_default().filter,
+            Err(e) => {
+                eprintln!("Error while reading settings, user id = {}: {:?}", acc, e);
+                bot.send_message(msg.chat.id, Text::DatabaseReadError(acc).localize(lang))
                     .await?;
+                return Ok(());
             }
-        }
+        };
+        bot.send_message(msg.chat.id, Text::NotifyPrompt(filter).localize(lang))
+            .reply_markup(make_notify_keyboard(lang))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn language(&mut self, bot: &Bot, msg: Message) -> ResponseResult<()> {
+        let Some(user) = msg.from else {
+            send_no_user(msg.chat.id, bot).await?;
+            return Ok(());
+        };
+        let acc = user.id.0;
+        let lang = self.lang_of(&user).await;
+        let chosen = match self.settings.lock().await.get(acc) {
+            Ok(settings) => settings
+                .and_then(|s| s.language)
+                .map(|tag| Lang::from_tag(&tag)),
+            Err(e) => {
+                eprintln!("Error while reading settings, user id = {}: {:?}", acc, e);
+                None
+            }
+        };
+        bot.send_message(
+            msg.chat.id,
+            Text::LanguagePrompt(chosen, lang).localize(lang),
+        )
+        .reply_markup(make_language_keyboard(lang))
+        .await?;
         Ok(())
     }
 
-    pub async fn interval_callback_handler(
+    pub async fn adaptive(
         &mut self,
         bot: &Bot,
-        query: CallbackQuery,
+        msg: Message,
+        bounds: String,
     ) -> ResponseResult<()> {
-        match query.data {
-            None => {
-                bot.send_message(query.from.id, "Your invalid click has no effect.")
-                    .await?;
+        let Some(user) = msg.from else {
+            send_no_user(msg.chat.id, bot).await?;
+            return Ok(());
+        };
+        let acc = user.id.0;
+        let lang = self.lang_of(&user).await;
+        let (min, max) = self.limits.bounds(acc);
+        let bounds = match parse_adaptive_bounds(&bounds).and_then(|bounds| match bounds {
+            Some(b) if b.min < min || b.max > max => Err(Text::IntervalOutOfRange(min, max)),
+            bounds => Ok(bounds),
+        }) {
+            Ok(bounds) => bounds,
+            Err(reason) => {
+                bot.send_message(msg.chat.id, reason.localize(lang)).await?;
+                return Ok(());
             }
-            Some(ref min) => match min.parse::<u32>() {
-                Ok(min) => {
-                    let acc = &query.from.id.0;
-                    let duration = Duration::from_secs(min as u64 * 60);
-                    let result: String = if let Some(e) = (if min > 0 {
-                        self.intervals
-                            .lock()
-                            .await
-                            .put(*acc, duration)
-                            .err()
-                    } else {
-                        self.intervals.lock().await.revoke(*acc).err()
-                    }) {
-                        eprintln!(
-                            "Error while updating interval database, user id = {}: {:?}",
-                            acc, e
-                        );
-                        format!(
-                            "Failed to update database. {}",
-                            get_contact_admin_text(*acc)
-                        )
-                    } else {
-                        self.ic_tx.send((*acc, duration)).await.expect(
-                            format!("Failed to notify interval change, user id = {}", *acc)
-                                .as_str(),
-                        );
-                        if min > 0 {
-                            format!("Polling interval has been updated to {min}min.")
-                        } else {
-                            "Polling has been disabled.".into()
-                        }
-                    };
-                    if let Some(msg) = query.regular_message() {
-                        bot.edit_message_text(msg.chat.id, msg.id, result).await?;
-                    } else if let Some(id) = query.inline_message_id {
-                        bot.edit_message_text_inline(id, result).await?;
-                    }
-                    bot.answer_callback_query(&query.id).await?;
+        };
+        let update_result = self
+            .update_settings(acc, |s| {
+                s.adaptive = bounds;
+                if let Some(bounds) = bounds {
+                    s.interval = Some(bounds.min);
                 }
-                Err(_) => {
+            })
+            .await;
+        let reply = match (update_result, bounds) {
+            (Err(e), _) => {
+                eprintln!("Error while updating settings, user id = {}: {:?}", acc, e);
+                Text::DatabaseError(acc)
+            }
+            (Ok(_), Some(bounds)) => {
+                self.notify_schedule(ScheduleChange::Reschedule(Job::Poll(acc), bounds.min))
+                    .await;
+                Text::AdaptiveOn(bounds.min, bounds.max)
+            }
+            (Ok(_), None) => Text::AdaptiveOff,
+        };
+        bot.send_message(msg.chat.id, reply.localize(lang)).await?;
+        Ok(())
+    }
+
+    pub async fn quiet(&mut self, bot: &Bot, msg: Message, hours: String) -> ResponseResult<()> {
+        let Some(user) = msg.from else {
+            send_no_user(msg.chat.id, bot).await?;
+            return Ok(());
+        };
+        let acc = user.id.0;
+        let lang = self.lang_of(&user).await;
+        let quiet_hours = if hours.trim().is_empty() {
+            None
+        } else {
+            match hours.parse::<QuietHours>() {
+                Ok(quiet_hours) => Some(quiet_hours),
+                Err(reason) => {
                     bot.send_message(
-                        query.from.id,
-                        "Your message carries invalid data thus has no effect.",
+                        msg.chat.id,
+                        Text::InvalidQuietHours(reason.into()).localize(lang),
                     )
                     .await?;
+                    return Ok(());
+                }
+            }
+        };
+        let reply = match self
+            .update_settings(acc, |s| s.quiet_hours = quiet_hours.clone())
+            .await
+        {
+            Err(e) => {
+                eprintln!("Error while updating settings, user id = {}: {:?}", acc, e);
+                Text::DatabaseError(acc)
+            }
+            Ok(_) => match quiet_hours {
+                Some(q) => Text::QuietOn(q),
+                None => Text::QuietOff,
+            },
+        };
+        bot.send_message(msg.chat.id, reply.localize(lang)).await?;
+        Ok(())
+    }
+
+    pub async fn refresh(&mut self, bot: &Bot, msg: Message) -> ResponseResult<()> {
+        let Some(user) = msg.from else {
+            send_no_user(msg.chat.id, bot).await?;
+            return Ok(());
+        };
+        let acc = user.id.0;
+        let lang = self.lang_of(&user).await;
+        if !self.clients.lock().await.contains(acc) {
+            bot.send_message(msg.chat.id, Text::NoToken.localize(lang))
+                .await?;
+            return Ok(());
+        }
+        // answered by the poll itself
+        self.notify_schedule(ScheduleChange::RunNow(Job::Poll(acc)))
+            .await;
+        Ok(())
+    }
+
+    pub async fn webhook(&mut self, bot: &Bot, msg: Message, url: String) -> ResponseResult<()> {
+        let Some(user) = msg.from else {
+            send_no_user(msg.chat.id, bot).await?;
+            return Ok(());
+        };
+        let acc = user.id.0;
+        let lang = self.lang_of(&user).await;
+        let url = url.trim();
+        let target = if url.is_empty() {
+            None
+        } else {
+            let rejection = match reqwest::Url::parse(url) {
+                Ok(parsed) if parsed.scheme() == "https" => {
+                    (!webhook::is_public(&parsed).await).then_some(Text::WebhookNotPublic)
                 }
+                _ => Some(Text::WebhookUsage),
+            };
+            if let Some(rejection) = rejection {
+                bot.send_message(msg.chat.id, rejection.localize(lang))
+                    .await?;
+                return Ok(());
+            }
+            let mut secret = [0u8; 32];
+            rand::thread_rng().fill_bytes(&mut secret);
+            Some(WebhookTarget {
+                url: url.to_string(),
+                secret: BASE64_URL_SAFE_NO_PAD.encode(secret),
+            })
+        };
+        let reply = match self
+            .update_settings(acc, |s| s.webhook = target.clone())
+            .await
+        {
+            Err(e) => {
+                eprintln!("Error while updating settings, user id = {}: {:?}", acc, e);
+                Text::DatabaseError(acc)
+            }
+            Ok(_) => match target {
+                Some(target) => Text::WebhookSet {
+                    url: target.url,
+                    secret: target.secret,
+                },
+                None => Text::WebhookOff,
             },
+        };
+        bot.send_message(msg.chat.id, reply.localize(lang)).await?;
+        Ok(())
+    }
+
+    pub async fn email(&mut self, bot: &Bot, msg: Message, address: String) -> ResponseResult<()> {
+        let Some(user) = msg.from else {
+            send_no_user(msg.chat.id, bot).await?;
+            return Ok(());
+        };
+        let acc = user.id.0;
+        let lang = self.lang_of(&user).await;
+        let Some(mailer) = self.mailer.clone() else {
+            bot.send_message(msg.chat.id, Text::EmailUnavailable.localize(lang))
+                .await?;
+            return Ok(());
+        };
+        let arg = address.trim();
+        let updated = if arg.is_empty() {
+            self.update_settings(acc, |s| s.email = None)
+                .await
+                .map(|_| Text::EmailOff)
+        } else if arg.len() == 6 && arg.bytes().all(|b| b.is_ascii_digit()) {
+            let mut reply = Text::EmailNothingPending;
+            self.update_settings(acc, |s| reply = confirm_email(&mut s.email, arg))
+                .await
+                .map(|_| reply)
+        } else if arg.parse::<lettre::Address>().is_err() {
+            Ok(Text::EmailUsage)
+        } else {
+            let now = self.clock.system_now();
+            let mut noted = Ok(());
+            let recorded = self
+                .update_settings(acc, |s| noted = note_code_send(&mut s.code_sends, now))
+                .await;
+            match (recorded, noted) {
+                (Err(e), _) => Err(e),
+                (Ok(_), Err(wait)) => Ok(Text::EmailTooManySends(wait)),
+                (Ok(_), Ok(_)) => {
+                    let code = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
+                    match mailer.send(arg, Letter::confirmation(&code, lang)).await {
+                        Err(e) => {
+                            eprintln!(
+                                "Error while sending confirmation code, user id = {}: {}",
+                                acc, e
+                            );
+                            Ok(Text::EmailSendFailed(arg.to_string()))
+                        }
+                        Ok(_) => {
+                            let target = EmailTarget {
+                                address: arg.to_string(),
+                                code: Some(code),
+                                attempts: 0,
+                            };
+                            self.update_settings(acc, |s| s.email = Some(target))
+                                .await
+                                .map(|_| Text::EmailCodeSent(arg.to_string()))
+                        }
+                    }
+                }
+            }
+        };
+        let reply = match updated {
+            Err(e) => {
+                eprintln!("Error while updating settings, user id = {}: {:?}", acc, e);
+                Text::DatabaseError(acc)
+            }
+            Ok(reply) => reply,
+        };
+        bot.send_message(msg.chat.id, reply.localize(lang)).await?;
+        Ok(())
+    }
+
+    pub async fn callback_handler(
+        &mut self,
+        bot: &Bot,
+        query: CallbackQuery,
+    ) -> ResponseResult<()> {
+        let lang = self.lang_of(&query.from).await;
+        match query.data.as_deref().map(Callback::parse) {
+            None => {
+                bot.send_message(query.from.id, Text::InvalidClick.localize(lang))
+                    .await?;
+            }
+            Some(Some(Callback::Interval(min))) => {
+                self.interval_callback(bot, &query, min, lang).await?;
+            }
+            Some(Some(Callback::ForgetMe(confirmed))) => {
+                self.forgetme_callback(bot, &query, confirmed, lang).await?;
+            }
+            Some(Some(Callback::Notify(filter))) => {
+                self.notify_callback(bot, &query, filter, lang).await?;
+            }
+            Some(Some(Callback::Language(chosen))) => {
+                self.language_callback(bot, &query, chosen).await?;
+            }
+            Some(None) => {
+                bot.send_message(query.from.id, Text::InvalidCallbackData.localize(lang))
+                    .await?;
+            }
         }
+        bot.answer_callback_query(&query.id).await?;
         Ok(())
     }
 
+    async fn interval_callback(
+        &mut self,
+        bot: &Bot,
+        query: &CallbackQuery,
+        min: u32,
+        lang: Lang,
+    ) -> ResponseResult<()> {
+        let acc = query.from.id.0;
+        let interval = (min > 0).then(|| Duration::from_secs(min as u64 * 60));
+        // the keyboard may have been sent before the limits changed
+        let checked = match interval {
+            Some(interval) => self.limits.check(acc, interval),
+            None => Ok(()),
+        };
+        let result = match checked {
+            Ok(_) => self.set_interval(acc, interval).await,
+            Err(reason) => reason,
+        };
+        edit_callback_message(bot, query, result.localize(lang)).await
+    }
+
+    async fn notify_callback(
+        &mut self,
+        bot: &Bot,
+        query: &CallbackQuery,
+        filter: NotificationFilter,
+        lang: Lang,
+    ) -> ResponseResult<()> {
+        let acc = query.from.id.0;
+        let result = match self.update_settings(acc, |s| s.filter = filter).await {
+            Err(e) => {
+                eprintln!("Error while updating settings, user id = {}: {:?}", acc, e);
+                Text::DatabaseError(acc)
+            }
+            Ok(_) => Text::NotifySet(filter),
+        };
+        edit_callback_message(bot, query, result.localize(lang)).await
+    }
+
+    /// Stores the language picked, answering in it right away.
+    async fn language_callback(
+        &mut self,
+        bot: &Bot,
+        query: &CallbackQuery,
+        chosen: Option<Lang>,
+    ) -> ResponseResult<()> {
+        let acc = query.from.id.0;
+        let mut lang = Lang::default();
+        let client_language = query.from.language_code.clone();
+        let result = match self
+            .update_settings(acc, |s| {
+                s.language = chosen.map(|lang| lang.tag().to_string());
+                s.client_language = client_language.or(s.client_language.take());
+                lang = Lang::of(s);
+            })
+            .await
+        {
+            Err(e) => {
+                eprintln!("Error while updating settings, user id = {}: {:?}", acc, e);
+                Text::DatabaseError(acc)
+            }
+            Ok(_) if chosen.is_some() => Text::LanguageSet(lang),
+            Ok(_) => Text::LanguageFollowing(lang),
+        };
+        edit_callback_message(bot, query, result.localize(lang)).await
+    }
+
     pub async fn signout(&mut self, bot: &Bot, msg: Message) -> ResponseResult<()> {
         match msg.from {
             None => {
@@ -227,175 +999,1954 @@ where
             }
             Some(user) => {
                 let acc_idx = user.id.0;
-                match self.cache.lock().await.revoke(acc_idx) {
-                    Ok(Some(_)) => {
+                let lang = self.lang_of(&user).await;
+                let reply = match self.unregister(acc_idx).await {
+                    Ok(true) => {
                         self.clients.lock().await.remove(acc_idx).await;
-                        bot.send_message(msg.chat.id, "Revoked previously stored token.")
-                            .await?;
-                    }
-                    Ok(None) => {
-                        bot.send_message(
-                            msg.chat.id,
-                            "No stored token. This operation carries no effect.",
-                        )
-                        .await?;
+                        self.notify_schedule(ScheduleChange::Remove(Job::Poll(acc_idx)))
+                            .await;
+                        Text::SignedOut
                     }
+                    Ok(false) => Text::NothingToSignOut,
                     Err(e) => {
-                        eprintln!("Error while revoking token, user id = {}: {:?}", acc_idx, e);
-                        bot.send_message(
-                            msg.chat.id,
-                            format!(
-                                "Failed to update database. {}",
-                                get_contact_admin_text(acc_idx)
-                            ),
-                        )
-                        .await?;
+                        eprintln!("Error while revoking token, user id = {}: {}", acc_idx, e);
+                        Text::DatabaseError(acc_idx)
                     }
-                }
+                };
+                bot.send_message(msg.chat.id, reply.localize(lang)).await?;
             }
         }
         Ok(())
     }
-}
-
-pub struct Watch<AP: Repository<ApplicationProgress>, I: Repository<Duration>> {
-    bot: Arc<Bot>,
-    clients: Arc<Mutex<ClientCollection>>,
-    cache: Arc<Mutex<AP>>,
-    intervals: Arc<Mutex<I>>,
-    ic_rx: Receiver<(AccountIndex, Duration)>,
-}
 
-impl<AP, I> Watch<AP, I>
-where
-    AP: Repository<ApplicationProgress>,
-    <AP as Repository<ApplicationProgress>>::Err: Debug,
-    I: Repository<Duration>,
-    <I as Repository<Duration>>::Err: Debug,
-{
-    pub fn new(
-        bot: Arc<Bot>,
-        clients: Arc<Mutex<ClientCollection>>,
-        intervals: Arc<Mutex<I>>,
-        cache: Arc<Mutex<AP>>,
-        change_rx: Receiver<(AccountIndex, Duration)>,
-    ) -> Self {
-        Self {
-            bot,
-            clients,
-            cache,
-            intervals,
-            ic_rx: change_rx,
+    pub async fn forgetme(&mut self, bot: &Bot, msg: Message) -> ResponseResult<()> {
+        match msg.from {
+            None => {
+                send_no_user(msg.chat.id, bot).await?;
+            }
+            Some(user) => {
+                let lang = self.lang_of(&user).await;
+                bot.send_message(msg.chat.id, Text::ForgetMePrompt.localize(lang))
+                    .reply_markup(make_forgetme_keyboard(lang))
+                    .await?;
+            }
         }
+        Ok(())
     }
 
-    async fn notify_if_applicable(&self, account: AccountIndex) {
-        match self.get_status_changes(account).await {
+    async fn forgetme_callback(
+        &mut self,
+        bot: &Bot,
+        query: &CallbackQuery,
+        confirmed: bool,
+        lang: Lang,
+    ) -> ResponseResult<()> {
+        let acc = query.from.id.0;
+        let result = if !confirmed {
+            Text::NothingErased
+        } else {
+            match self.erase_account(acc).await {
+                Ok(_) => {
+                    self.clients.lock().await.remove(acc).await;
+                    self.notify_schedule(ScheduleChange::Remove(Job::Poll(acc)))
+                        .await;
+                    Text::AllErased
+                }
+                Err(e) => {
+                    eprintln!("Error while erasing account, user id = {}: {}", acc, e);
+                    Text::DatabaseError(acc)
+                }
+            }
+        };
+        edit_callback_message(bot, query, result.localize(lang)).await
+    }
+}
+
+/// Fetches an account's progress and tells its owner about changes. Shared by the
+/// concurrent poll tasks spawned from [`Watch`].
+struct Poller<AP, L, S>
+where
+    AP: Repository<ApplicationProgress>,
+    L: Repository<Ledger>,
+    S: Repository<UserSettings>,
+{
+    bot: Arc<Bot>,
+    clients: Arc<Mutex<ClientCollection>>,
+    cache: Arc<Mutex<AP>>,
+    settings: Arc<Mutex<S>>,
+    /// What was notified, and what is still to be, for each account.
+    ledger: Arc<Mutex<L>>,
+    /// Accounts with notifications held until their quiet hours end.
+    releasing: Mutex<HashSet<AccountIndex>>,
+    /// Taken while pushing to an account, so a poll and a release can't both push
+    /// the same notifications.
+    delivering: Mutex<HashMap<AccountIndex, Arc<Mutex<()>>>>,
+    /// Age after which a notification is dropped rather than delivered late.
+    max_age: Duration,
+    /// Channel whose deliveries the outbox keeps track of.
+    notifier: Arc<dyn Notifier>,
+    forwarders: Vec<Arc<dyn Notifier>>,
+    clock: SharedClock,
+}
+
+impl<AP, L, S> Poller<AP, L, S>
+where
+    AP: TransactionalRepository<ApplicationProgress> + Send + 'static,
+    <AP as Repository<ApplicationProgress>>::Err: Debug,
+    L: TransactionalRepository<Ledger, Unit = AP::Unit> + Send + 'static,
+    <L as Repository<Ledger>>::Err: Debug,
+    S: Repository<UserSettings> + Send + 'static,
+    <S as Repository<UserSettings>>::Err: Debug,
+    <AP::Unit as UnitOfWork>::Err: Debug,
+{
+    /// Polls the account and records what changed, notifying of it if the filter
+    /// lets it through, then pushes whatever is pending unless it falls into the
+    /// quiet hours. Returns what was observed for pacing the following polls. A
+    /// poll the user asked for is answered even when nothing changed.
+    async fn notify_if_applicable(
+        self: &Arc<Self>,
+        account: AccountIndex,
+        quiet_hours: Option<QuietHours>,
+        filter: NotificationFilter,
+        requested: bool,
+    ) -> Outcome {
+        let outcome = match self.get_status_changes(account).await {
             Ok(Some(change)) => {
-                let push_result = self
-                    .bot
-                    .send_message(UserId(account), format!("Progress update: {}", change))
-                    .await;
-                if let Err(e) = push_result {
-                    println!("Error while pushing: {}, user id = {}", e, account)
+                match self.record(account, &change, filter).await {
+                    Err(e) => {
+                        eprintln!(
+                            "Error while recording progress, user id = {}: {}",
+                            account, e
+                        );
+                        return Outcome::Done;
+                    }
+                    Ok(false) if requested => {
+                        self.reply(account, Text::FilteredOut).await;
+                    }
+                    Ok(_) => {}
                 }
+                let StatusChange::Progress(ap) = change;
+                Outcome::Observed(Observation::Changed(ap.get_current_step().ok().flatten()))
             }
-            Ok(None) => {}
+            Ok(None) => {
+                if requested {
+                    self.reply(account, Text::NothingChanged).await;
+                }
+                Outcome::Observed(Observation::Unchanged)
+            }
+            Err(crate::tencent::error::Error::TokenExpired) => Outcome::Expired(vec![account]),
             Err(e) => {
-                eprintln!("Error while monitoring: {}, user id = {}", e, account)
+                eprintln!("Error while monitoring: {}, user id = {}", e, account);
+                if requested {
+                    self.reply(account, Text::FetchFailed(e.to_string())).await;
+                }
+                Outcome::Done
+            }
+        };
+        // also catches up on what was left pending by an earlier run
+        self.deliver(account, quiet_hours).await;
+        outcome
+    }
+
+    /// Language the account's owner reads, English if the settings can't be read.
+    async fn lang(&self, account: AccountIndex) -> Lang {
+        match self.settings.lock().await.get(account) {
+            Ok(settings) => Lang::of(&settings.unwrap_or_default()),
+            Err(e) => {
+                eprintln!(
+                    "Error while reading settings, user id = {}: {:?}",
+                    account, e
+                );
+                Lang::default()
             }
         }
     }
 
-    async fn get_status_changes(
+    async fn reply(&self, account: AccountIndex, text: Text) {
+        let text = text.localize(self.lang(account).await);
+        if let Err(e) = self.bot.send_message(UserId(account), text).await {
+            eprintln!("Error while replying: {}, user id = {}", e, account)
+        }
+    }
+
+    /// Stores the progress along with the notification it calls for, so neither
+    /// is kept without the other. Changes the filter leaves out are stored without
+    /// one. Returns whether there was a notification.
+    async fn record(
         &self,
         account: AccountIndex,
-    ) -> ClientResult<Option<StatusChange>> {
-        let old_progress = self.cache.lock().await.get(account).expect(
-            format!(
-                "Database failed to query progress cache, user id = {}",
-                account
-            )
-            .as_str(),
-        );
-        let curr = self
-            .clients
-            .lock()
-            .await
+        change: &StatusChange,
+        filter: NotificationFilter,
+    ) -> Result<bool, StoreError> {
+        let StatusChange::Progress(progress) = change;
+        // the summary is kept as sent, so it's written in the language of the day
+        let lang = self.lang(account).await;
+        let cache = self.cache.lock().await;
+        let ledger = self.ledger.lock().await;
+        let before = cache.get(account).map_err(StoreError::of)?;
+        let notify = change.passes(filter, before.as_ref());
+        let now = self.clock.system_now();
+        let mut entries = ledger
             .get(account)
-            .await
-            .expect(format!("Missing client, user id = {}", account).as_str())
-            .to_owned()
+            .map_err(StoreError::of)?
+            .unwrap_or_default();
+        entries.note_change(now);
+        if notify {
+            entries.record(
+                change.summary(lang),
+                change.is_urgent(),
+                change.snapshot(before.as_ref()),
+                now,
+            );
+        }
+        let unit = cache.begin().map_err(StoreError::of)?;
+        cache
+            .put_in(&unit, account, progress.as_ref().clone())
+            .map_err(StoreError::of)?;
+        ledger
+            .put_in(&unit, account, entries)
+            .map_err(StoreError::of)?;
+        unit.commit().map_err(StoreError::of)?;
+        Ok(notify)
+    }
+
+    /// Pushes the pending notifications, unless it falls into the quiet hours. Those
+    /// held back are released when the quiet hours end.
+    async fn deliver(self: &Arc<Self>, account: AccountIndex, quiet_hours: Option<QuietHours>) {
+        let now = self.clock.system_now();
+        let quiet = quiet_hours.filter(|quiet| quiet.contains(now));
+        if let Some(quiet) = quiet {
+            if self.push_pending(account, Some(&quiet)).await {
+                self.release(account, quiet.until_end(now)).await;
+            }
+        } else {
+            self.push_pending(account, None).await;
+        }
+    }
+
+    /// Pushes the pending notifications as one message and marks them sent, or
+    /// backs off if that fails. Those not forwarded yet are forwarded first, apart
+    /// from Telegram's backoff. During quiet hours, only urgent ones go out if the
+    /// user let them. Notifications too old to matter are dropped instead. Returns
+    /// whether any were held back.
+    async fn push_pending(&self, account: AccountIndex, quiet: Option<&QuietHours>) -> bool {
+        let delivering = self
+            .delivering
             .lock()
             .await
-            .get_application_progress()
+            .entry(account)
+            .or_default()
+            .clone();
+        let _delivering = delivering.lock().await;
+        let mut ledger = match self.ledger.lock().await.get(account) {
+            Ok(ledger) => ledger.unwrap_or_default(),
+            Err(e) => {
+                eprintln!("Error while reading ledger, user id = {}: {:?}", account, e);
+                return false;
+            }
+        };
+        let now = self.clock.system_now();
+        let oldest = now
+            .checked_sub(self.max_age)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        let expired = ledger.expire(oldest);
+        if expired > 0 {
+            eprintln!(
+                "Dropped {} undelivered notifications, user id = {}",
+                expired, account
+            );
+            self.update_ledger(account, |ledger| {
+                ledger.expire(oldest);
+            })
             .await;
+        }
+        let (due, held): (Vec<_>, Vec<_>) = ledger
+            .pending()
+            .partition(|n| quiet.is_none_or(|q| q.urgent && n.urgent));
+        let unforwarded: Vec<_> = due
+            .iter()
+            .filter(|n| !n.forwarded)
+            .map(|&n| n.clone())
+            .collect();
+        if !unforwarded.is_empty() && !self.forwarders.is_empty() {
+            let ids: Vec<_> = unforwarded.iter().map(|n| n.id).collect();
+            self.update_ledger(account, |ledger| ledger.mark_forwarded(&ids))
+                .await;
+            self.forward(account, unforwarded);
+        }
+        if ledger.retry().is_some_and(|retry| retry.at > now) {
+            // left to the outbox
+            return false;
+        }
+        if due.is_empty() {
+            return !held.is_empty();
+        }
+        let due: Vec<_> = due.into_iter().cloned().collect();
+        match self.notifier.notify(account, &due).await {
+            Ok(_) => {
+                let ids: Vec<_> = due.iter().map(|n| n.id).collect();
+                self.update_ledger(account, |ledger| ledger.mark_sent(&ids, now))
+                    .await;
+            }
+            Err(e) => {
+                let mut retry = None;
+                self.update_ledger(account, |ledger| {
+                    retry = Some(ledger.fail(now, e.retry_after()))
+                })
+                .await;
+                eprintln!(
+                    "Error while pushing: {}, user id = {}, retrying in {}s",
+                    e,
+                    account,
+                    retry.map_or(0, |at| at.duration_since(now).unwrap_or_default().as_secs())
+                );
+            }
+        }
+        !held.is_empty()
+    }
+
+    /// Hands notifications to the other channels, each on its own, whether or not
+    /// Telegram gets through. They retry on their own instead of through the
+    /// outbox, so each notification is handed over once.
+    fn forward(&self, account: AccountIndex, changes: Vec<Notification>) {
+        let changes = Arc::new(changes);
+        for forwarder in &self.forwarders {
+            let (forwarder, changes) = (forwarder.clone(), changes.clone());
+            tokio::spawn(async move {
+                if let Err(e) = forwarder.notify(account, &changes).await {
+                    eprintln!("Error while forwarding: {}, user id = {}", e, account)
+                }
+            });
+        }
+    }
+
+    async fn update_ledger(&self, account: AccountIndex, update: impl FnOnce(&mut Ledger)) {
+        let mut ledger = self.ledger.lock().await;
+        let result = ledger.get(account).and_then(|entries| {
+            let mut entries = entries.unwrap_or_default();
+            update(&mut entries);
+            ledger.put(account, entries)
+        });
+        if let Err(e) = result {
+            eprintln!(
+                "Error while updating ledger, user id = {}: {:?}",
+                account, e
+            );
+        }
+    }
+
+    /// Runs [`Job::Outbox`], delivering whatever is pending and not backing off.
+    async fn retry_pending(self: &Arc<Self>) {
+        let accounts: Vec<_> = match self.ledger.lock().await.entries() {
+            Ok(entries) => entries
+                .filter(|(_, ledger)| ledger.pending().next().is_some())
+                .map(|(acc, _)| acc)
+                .collect(),
+            Err(e) => {
+                eprintln!("Error while listing ledger: {:?}", e);
+                return;
+            }
+        };
+        for acc in accounts {
+            let quiet_hours = match self.settings.lock().await.get(acc) {
+                Ok(settings) => settings.and_then(|s| s.quiet_hours),
+                Err(e) => {
+                    eprintln!("Error while reading settings, user id = {}: {:?}", acc, e);
+                    continue;
+                }
+            };
+            self.deliver(acc, quiet_hours).await;
+        }
+    }
+
+    /// Pushes what's pending for the account once the quiet hours end.
+    async fn release(self: &Arc<Self>, account: AccountIndex, until_end: Duration) {
+        if !self.releasing.lock().await.insert(account) {
+            // released along with the first one
+            return;
+        }
+        let poller = self.clone();
+        let end = self.clock.sleep_until(self.clock.now() + until_end);
+        tokio::spawn(async move {
+            end.await;
+            poller.releasing.lock().await.remove(&account);
+            poller.push_pending(account, None).await;
+        });
+    }
+
+    async fn get_status_changes(
+        &self,
+        account: AccountIndex,
+    ) -> ClientResult<Option<StatusChange>> {
+        let old_progress = self.cache.lock().await.get(account).unwrap_or_else(|e| {
+            panic!(
+                "Database failed to query progress cache, user id = {}: {:?}",
+                account, e
+            )
+        });
+        // signed out since this poll was scheduled
+        let Some(client) = self.clients.lock().await.get(account).await else {
+            return Ok(None);
+        };
+        let curr = client.lock().await.get_application_progress().await;
         match curr {
             Ok(progress) => {
-                if old_progress.map_or(true, |o| o != progress) {
-                    Ok(Some(StatusChange::Progress(progress)))
+                if old_progress.is_none_or(|o| o != progress) {
+                    Ok(Some(StatusChange::Progress(Box::new(progress))))
                 } else {
                     Ok(None)
                 }
             }
-            Err(crate::tencent::error::Error::TokenExpired) => Ok(Some(StatusChange::Expiry)),
             Err(e) => Err(e),
         }
     }
+}
 
-    pub async fn start_monitoring(&mut self) {
-        let mut watch: Watcher<AccountIndex> = self
-            .intervals
+/// Window over which jobs that fell due while the bot was down are spread.
+const OVERDUE_WINDOW: Duration = Duration::from_secs(5 * 60);
+/// Time after which a poll is given up on, so it can't hold a slot forever.
+const POLL_TIMEOUT: Duration = Duration::from_secs(60);
+/// Least time between two polls a user asks for.
+const REFRESH_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// What a finished task tells the monitoring loop.
+enum Outcome {
+    Observed(Observation),
+    /// Tokens of these accounts turned out to have expired.
+    Expired(Vec<AccountIndex>),
+    Done,
+}
+
+/// How [`Watch`] paces the polls, and which maintenance jobs it runs besides.
+pub struct WatchConfig {
+    /// Number of polls allowed to run at the same time.
+    pub max_concurrent: usize,
+    pub jitter: Jitter,
+    /// Maintenance jobs along with when they recur. Jobs left out don't run.
+    pub maintenance: HashMap<Job, Recurrence>,
+    /// Required for [`Job::Backup`] to do anything.
+    pub backup: Option<Backup>,
+    /// Age after which a notification that couldn't be delivered is dropped.
+    pub outbox_max_age: Duration,
+    /// Channels notifications go to once delivered on Telegram.
+    pub forwarders: Vec<Arc<dyn Notifier>>,
+    pub clock: SharedClock,
+}
+
+pub struct Watch<R, AP, S, D, L>
+where
+    R: Repository<String>,
+    AP: Repository<ApplicationProgress>,
+    S: Repository<UserSettings>,
+    D: ScheduleRepository,
+    L: Repository<Ledger>,
+{
+    /// Only read, to tell which accounts are still signed in.
+    tokens: R,
+    poller: Arc<Poller<AP, L, S>>,
+    settings: Arc<Mutex<S>>,
+    schedule: D,
+    ic_rx: Receiver<ScheduleChange<Job>>,
+    permits: Arc<Semaphore>,
+    /// Job and nominal deadline of every task running.
+    in_flight: HashMap<task::Id, (Job, Instant)>,
+    paces: HashMap<AccountIndex, Pace>,
+    /// The last poll each account asked for with /refresh.
+    requested: HashMap<AccountIndex, Refresh>,
+    /// Accounts paused for an expired token, along with the number of reminders
+    /// sent. The count is kept in memory only, so a restart begins the reminders
+    /// anew, while the pause itself is kept as the due time of [`Job::Reauth`].
+    expired: HashMap<AccountIndex, u32>,
+    jitter: Jitter,
+    maintenance: HashMap<Job, Recurrence>,
+    backup: Option<Arc<Backup>>,
+    clock: SharedClock,
+}
+
+/// A poll asked for with /refresh.
+struct Refresh {
+    /// When it was asked for, which the cooldown counts from.
+    at: Instant,
+    /// Whether its run is yet to start.
+    pending: bool,
+}
+
+impl<R, AP, S, D, L> Watch<R, AP, S, D, L>
+where
+    R: TransactionalRepository<String, Unit = AP::Unit>,
+    <R as Repository<String>>::Err: Debug,
+    AP: TransactionalRepository<ApplicationProgress> + Send + 'static,
+    <AP as Repository<ApplicationProgress>>::Err: Debug,
+    S: Repository<UserSettings> + Send + 'static,
+    <S as Repository<UserSettings>>::Err: Debug,
+    D: ScheduleRepository,
+    <D as ScheduleRepository>::Err: Debug,
+    L: TransactionalRepository<Ledger, Unit = AP::Unit> + Send + 'static,
+    <L as Repository<Ledger>>::Err: Debug,
+    <AP::Unit as UnitOfWork>::Err: Debug,
+{
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        bot: Arc<Bot>,
+        tokens: R,
+        clients: Arc<Mutex<ClientCollection>>,
+        settings: Arc<Mutex<S>>,
+        cache: Arc<Mutex<AP>>,
+        ledger: Arc<Mutex<L>>,
+        schedule: D,
+        change_rx: Receiver<ScheduleChange<Job>>,
+        config: WatchConfig,
+    ) -> Self {
+        Self {
+            tokens,
+            poller: Arc::new(Poller {
+                bot: bot.clone(),
+                clients,
+                cache,
+                settings: settings.clone(),
+                ledger,
+                releasing: Mutex::new(HashSet::new()),
+                delivering: Mutex::new(HashMap::new()),
+                max_age: config.outbox_max_age,
+                notifier: Arc::new(Telegram::new(bot.clone(), settings.clone())),
+                forwarders: config.forwarders,
+                clock: config.clock.clone(),
+            }),
+            settings,
+            schedule,
+            ic_rx: change_rx,
+            permits: Arc::new(Semaphore::new(config.max_concurrent.max(1))),
+            in_flight: HashMap::new(),
+            paces: HashMap::new(),
+            requested: HashMap::new(),
+            expired: HashMap::new(),
+            jitter: config.jitter,
+            maintenance: config.maintenance,
+            backup: config.backup.map(Arc::new),
+            clock: config.clock,
+        }
+    }
+
+    /// Records when the job is due next, or that it isn't scheduled at all, so a
+    /// restart picks up where this run left off.
+    fn persist_due(&mut self, job: Job, deadline: Option<Instant>) {
+        let key = job.to_string();
+        let result = match deadline {
+            Some(deadline) => {
+                let due =
+                    self.clock.system_now() + deadline.saturating_duration_since(self.clock.now());
+                self.schedule.set_due(&key, due)
+            }
+            None => self.schedule.clear(&key),
+        };
+        if let Err(e) = result {
+            eprintln!("Error while persisting schedule of {}: {:?}", key, e);
+        }
+    }
+
+    fn stored_due(&self, job: Job) -> Option<SystemTime> {
+        self.schedule.due(&job.to_string()).unwrap_or_else(|e| {
+            eprintln!("Error while reading schedule of {}: {:?}", job, e);
+            None
+        })
+    }
+
+    fn apply_change(&mut self, watch: &mut Watcher<Job>, change: ScheduleChange<Job>) {
+        match change {
+            // the new interval takes effect once polling resumes
+            ScheduleChange::Reschedule(Job::Poll(acc), _) if self.expired.contains_key(&acc) => {}
+            ScheduleChange::Reschedule(job, interval) => {
+                let deadline = watch.push_periodic(job, self.clock.now(), interval);
+                self.persist_due(job, Some(deadline));
+            }
+            ScheduleChange::Remove(job) => {
+                watch.remove(job);
+                if let Job::Poll(acc) = job {
+                    self.paces.remove(&acc);
+                    self.requested.remove(&acc);
+                    self.resume(watch, acc);
+                }
+                self.persist_due(job, None);
+            }
+            ScheduleChange::RunNow(Job::Poll(acc)) if self.expired.contains_key(&acc) => {
+                self.reply_later(acc, Text::Expired)
+            }
+            ScheduleChange::RunNow(job) => {
+                let now = self.clock.now();
+                if let Job::Poll(acc) = job {
+                    // answered now, as the poll under way stays silent if nothing
+                    // changed, and the cooldown is left for the next try
+                    if self.is_in_flight(job) {
+                        return self.reply_later(acc, Text::RefreshRunning);
+                    }
+                    if let Some(last) = self.requested.get(&acc) {
+                        if now < last.at + REFRESH_COOLDOWN {
+                            let wait = (last.at + REFRESH_COOLDOWN - now).as_secs().max(1);
+                            return self.reply_later(acc, Text::RefreshCooldown(wait));
+                        }
+                    }
+                    self.requested.insert(
+                        acc,
+                        Refresh {
+                            at: now,
+                            pending: true,
+                        },
+                    );
+                }
+                watch.push_at(job, now);
+            }
+            ScheduleChange::Resume(job) => {
+                if let Job::Poll(acc) = job {
+                    if self.resume(watch, acc) {
+                        // catches up on what happened while paused
+                        watch.push_at(job, self.clock.now());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Stops polling the account, whose token has expired, and tells its owner once.
+    /// Reminders follow at growing intervals until polling resumes.
+    fn pause(&mut self, watch: &mut Watcher<Job>, account: AccountIndex) {
+        if self.expired.contains_key(&account) {
+            return;
+        }
+        let poll = Job::Poll(account);
+        watch.remove(poll);
+        self.persist_due(poll, None);
+        self.paces.remove(&account);
+        self.requested.remove(&account);
+        self.expired.insert(account, 0);
+        let reminder = Job::Reauth(account);
+        let deadline = self.clock.now() + jobs::reminder_gap(0);
+        watch.push_at(reminder, deadline);
+        self.persist_due(reminder, Some(deadline));
+        self.reply_later(account, Text::Expired);
+    }
+
+    /// Stops reminding the account to sign in again, returning whether it was
+    /// paused.
+    fn resume(&mut self, watch: &mut Watcher<Job>, account: AccountIndex) -> bool {
+        if self.expired.remove(&account).is_none() {
+            return false;
+        }
+        watch.remove(Job::Reauth(account));
+        self.persist_due(Job::Reauth(account), None);
+        true
+    }
+
+    /// Runs [`Job::Reauth`] and schedules the next reminder further away.
+    fn remind(&mut self, watch: &mut Watcher<Job>, account: AccountIndex, due: Instant) {
+        let sent = self.expired.entry(account).or_default();
+        *sent += 1;
+        let job = Job::Reauth(account);
+        let deadline = due + jobs::reminder_gap(*sent);
+        watch.push_at(job, deadline);
+        self.persist_due(job, Some(deadline));
+        self.reply_later(account, Text::Reminder);
+    }
+
+    /// Sends the text in the background, so the monitoring loop carries on.
+    fn reply_later(&self, account: AccountIndex, text: Text) {
+        let poller = self.poller.clone();
+        tokio::spawn(async move { poller.reply(account, text).await });
+    }
+
+    async fn restore_schedule(&mut self) -> Watcher<Job> {
+        let reminders: Vec<_> = self
+            .schedule
+            .jobs()
+            .expect("Failed to list schedule")
+            .into_iter()
+            .filter_map(|key| match key.parse() {
+                Ok(Job::Reauth(acc)) => Some(acc),
+                _ => None,
+            })
+            .collect();
+        self.expired = reminders.iter().map(|&acc| (acc, 0)).collect();
+        let polled: Vec<_> = self
+            .settings
             .lock()
             .await
             .entries()
-            .expect("Failed to list user intervals")
+            .expect("Failed to list user settings")
+            .filter(|(acc, settings)| {
+                settings.interval.is_some() && !self.expired.contains_key(acc)
+            })
+            .map(|(acc, _)| Job::Poll(acc))
             .collect();
+        let now = self.clock.system_now();
+        let mut entries: Vec<_> = polled
+            .into_iter()
+            .chain(reminders.into_iter().map(Job::Reauth))
+            .map(|job| (job, self.stored_due(job)))
+            .collect();
+        for (&job, recurrence) in &self.maintenance {
+            let due = match self.stored_due(job) {
+                Some(due) => Some(due),
+                // a job that never ran waits for its first occurrence, which is
+                // stored right away so restarts don't keep putting it off
+                None => recurrence.first_after(now).inspect(|&due| {
+                    if let Err(e) = self.schedule.set_due(&job.to_string(), due) {
+                        eprintln!("Error while persisting schedule of {}: {:?}", job, e);
+                    }
+                }),
+            };
+            entries.push((job, due));
+        }
+        let mut watch = Watcher::with_clock(self.clock.clone(), self.jitter);
+        watch.restore(entries, OVERDUE_WINDOW);
+        watch
+    }
+
+    /// Schedules the job's next run and starts this one.
+    async fn run(
+        &mut self,
+        watch: &mut Watcher<Job>,
+        tasks: &mut JoinSet<Outcome>,
+        job: Job,
+        due: Instant,
+    ) {
+        if let Job::Reauth(acc) = job {
+            return self.remind(watch, acc, due);
+        }
+        let Job::Poll(acc) = job else {
+            let deadline = self
+                .maintenance
+                .get(&job)
+                .and_then(|recurrence| watch.push_recurring(job, due, recurrence));
+            self.persist_due(job, deadline);
+            return if job == Job::Cleanup {
+                self.clean_up(watch).await
+            } else {
+                self.dispatch(tasks, job, due, None, NotificationFilter::All, false)
+            };
+        };
+        let settings = self.settings_of(acc).await.unwrap_or_default();
+        let deadline = self
+            .next_interval(acc, &settings)
+            .map(|interval| watch.push_periodic(job, due, interval));
+        self.persist_due(job, deadline);
+        // asked for on the spot, so neither held back nor kept silent
+        let requested = self
+            .requested
+            .get_mut(&acc)
+            .is_some_and(|refresh| std::mem::take(&mut refresh.pending));
+        let quiet_hours = settings.quiet_hours.filter(|_| !requested);
+        self.dispatch(tasks, job, due, quiet_hours, settings.filter, requested);
+    }
+
+    fn is_in_flight(&self, job: Job) -> bool {
+        self.in_flight.values().any(|&(running, _)| running == job)
+    }
+
+    /// Starts the job in the background, unless its previous run is still going
+    /// on. At most [`WatchConfig::max_concurrent`] polls run at a time.
+    fn dispatch(
+        &mut self,
+        tasks: &mut JoinSet<Outcome>,
+        job: Job,
+        due: Instant,
+        quiet_hours: Option<QuietHours>,
+        filter: NotificationFilter,
+        requested: bool,
+    ) {
+        if self.is_in_flight(job) {
+            eprintln!("Skipping {} still in flight", job);
+            if let (Job::Poll(account), true) = (job, requested) {
+                self.reply_later(account, Text::RefreshRunning);
+            }
+            return;
+        }
+        let poller = self.poller.clone();
+        let handle = match job {
+            Job::Poll(account) => {
+                let permits = self.permits.clone();
+                tasks.spawn(async move {
+                    let _permit = permits.acquire_owned().await;
+                    let timeout = poller.clock.sleep_until(poller.clock.now() + POLL_TIMEOUT);
+                    select! {
+                        observation = poller.notify_if_applicable(account, quiet_hours, filter, requested) => observation,
+                        _ = timeout => {
+                            eprintln!("Poll timed out, user id = {}", account);
+                            Outcome::Done
+                        }
+                    }
+                })
+            }
+            Job::Digest => {
+                let settings = self.settings.clone();
+                let expired: HashSet<_> = self.expired.keys().copied().collect();
+                tasks.spawn(async move {
+                    jobs::send_digests(
+                        &poller.bot,
+                        &poller.clients,
+                        &settings,
+                        &poller.cache,
+                        &expired,
+                        &poller.clock,
+                    )
+                    .await;
+                    Outcome::Done
+                })
+            }
+            Job::TokenHealth => {
+                let settings = self.settings.clone();
+                tasks.spawn(async move {
+                    Outcome::Expired(jobs::check_tokens(&poller.clients, &settings).await)
+                })
+            }
+            Job::Outbox => tasks.spawn(async move {
+                poller.retry_pending().await;
+                Outcome::Done
+            }),
+            Job::Backup => {
+                let Some(backup) = self.backup.clone() else {
+                    eprintln!("Skipping backup, as no backup path is configured");
+                    return;
+                };
+                tasks.spawn_blocking(move || {
+                    backup.run();
+                    Outcome::Done
+                })
+            }
+            Job::Reauth(_) | Job::Cleanup => unreachable!("{} runs inline", job),
+        };
+        self.in_flight.insert(handle.id(), (job, due));
+    }
+
+    /// Runs [`Job::Cleanup`]. It needs the schedule, so it runs in the monitoring
+    /// loop rather than in the background.
+    async fn clean_up(&mut self, watch: &Watcher<Job>) {
+        let (orphans, unheard) = self.drop_signed_out().await.unwrap_or_else(|e| {
+            eprintln!("Error while dropping records of signed out accounts: {}", e);
+            (0, 0)
+        });
+
+        let stale: Vec<_> = match self.schedule.jobs() {
+            Ok(jobs) => jobs
+                .into_iter()
+                .filter(|key| !key.parse().is_ok_and(|job| watch.contains(job)))
+                .collect(),
+            Err(e) => {
+                eprintln!("Error while listing schedule: {:?}", e);
+                Vec::new()
+            }
+        };
+        for key in &stale {
+            if let Err(e) = self.schedule.clear(key) {
+                eprintln!("Error while dropping schedule of {}: {:?}", key, e);
+            }
+        }
+        self.paces.retain(|&acc, _| watch.contains(Job::Poll(acc)));
+        println!(
+            "Cleaned up {} stale progress records, {} stale ledgers and {} stale schedule entries",
+            orphans,
+            unheard,
+            stale.len()
+        );
+    }
+
+    /// Drops the progress and notifications of accounts without a stored token,
+    /// returning how many of each went. The candidates are listed up front, and
+    /// each is checked against the tokens in the unit of work that drops it, so an
+    /// account signing in meanwhile keeps its records.
+    async fn drop_signed_out(&self) -> Result<(usize, usize), StoreError> {
+        let cached: Vec<_> = self
+            .poller
+            .cache
+            .lock()
+            .await
+            .keys()
+            .map_err(StoreError::of)?
+            .collect();
+        let ledgers: Vec<_> = self
+            .poller
+            .ledger
+            .lock()
+            .await
+            .keys()
+            .map_err(StoreError::of)?
+            .collect();
+        if cached.is_empty() && ledgers.is_empty() {
+            return Ok((0, 0));
+        }
+
+        // the same lock order as signing out
+        let cache = self.poller.cache.lock().await;
+        let ledger = self.poller.ledger.lock().await;
+        let unit = self.tokens.begin().map_err(StoreError::of)?;
+        let signed_out = |acc| {
+            self.tokens
+                .contains_in(&unit, acc)
+                .map(|signed_in| !signed_in)
+                .map_err(StoreError::of)
+        };
+        let mut orphans = 0;
+        for acc in cached {
+            if signed_out(acc)?
+                && cache
+                    .revoke_in(&unit, acc)
+                    .map_err(StoreError::of)?
+                    .is_some()
+            {
+                orphans += 1;
+            }
+        }
+        let mut unheard = 0;
+        for acc in ledgers {
+            if signed_out(acc)?
+                && ledger
+                    .revoke_in(&unit, acc)
+                    .map_err(StoreError::of)?
+                    .is_some()
+            {
+                unheard += 1;
+            }
+        }
+        unit.commit().map_err(StoreError::of)?;
+        Ok((orphans, unheard))
+    }
+
+    async fn settings_of(&self, account: AccountIndex) -> Option<UserSettings> {
+        self.settings.lock().await.get(account).unwrap_or_else(|e| {
+            eprintln!(
+                "Error while reading settings, user id = {}: {:?}",
+                account, e
+            );
+            None
+        })
+    }
+
+    fn next_interval(&self, account: AccountIndex, settings: &UserSettings) -> Option<Duration> {
+        let pace = self.paces.get(&account).cloned().unwrap_or_default();
+        pace::next_interval(settings, &pace)
+    }
+
+    /// Feeds what a finished poll observed into the account's pace. Adaptive
+    /// accounts were scheduled before the poll ran, so they are rescheduled from the
+    /// same deadline in case the interval changed.
+    async fn observe(
+        &mut self,
+        watch: &mut Watcher<Job>,
+        account: AccountIndex,
+        due: Instant,
+        observation: Observation,
+    ) {
+        self.paces.entry(account).or_default().observe(observation);
+        let Some(settings) = self.settings_of(account).await else {
+            return;
+        };
+        let job = Job::Poll(account);
+        if settings.adaptive.is_none() || !watch.contains(job) {
+            return;
+        }
+        if let Some(interval) = self.next_interval(account, &settings) {
+            let deadline = watch.push_periodic(job, due, interval);
+            self.persist_due(job, Some(deadline));
+        }
+    }
+
+    pub async fn start_monitoring(&mut self) {
+        let mut watch = self.restore_schedule().await;
+        let mut tasks = JoinSet::new();
         loop {
             select! {
-                next = watch.next() => {
-                    match next {
-                        Some(acc) => {
-                            self.notify_if_applicable(acc).await;
-                            if let Ok(Some(d)) = self.intervals.lock().await.get(acc) {
-                                watch.push(acc, d);
+                Some((job, due)) = watch.next() => {
+                    self.run(&mut watch, &mut tasks, job, due).await
+                }
+                Some(change) = self.ic_rx.recv() => {
+                    self.apply_change(&mut watch, change)
+                }
+                Some(done) = tasks.join_next_with_id() => {
+                    let (id, outcome) = match done {
+                        Ok((id, outcome)) => (id, outcome),
+                        Err(e) => {
+                            eprintln!("Task failed: {}", e);
+                            (e.id(), Outcome::Done)
+                        }
+                    };
+                    let Some((job, due)) = self.in_flight.remove(&id) else {
+                        continue;
+                    };
+                    match (job, outcome) {
+                        (Job::Poll(acc), Outcome::Observed(observation)) => {
+                            self.observe(&mut watch, acc, due, observation).await
+                        }
+                        (_, Outcome::Expired(accounts)) => {
+                            for acc in accounts {
+                                self.pause(&mut watch, acc);
                             }
-                        },
-                        None => {
-                            self.ic_rx.recv().await;
                         }
+                        _ => {}
                     }
                 }
-                Some((acc, duration)) = self.ic_rx.recv() => {
-                    watch.push(acc, duration)
-                }
+                else => break,
             }
         }
     }
 }
 
 async fn send_no_user(chat_id: ChatId, bot: &Bot) -> ResponseResult<Message> {
-    bot.send_message(chat_id, "No user info bound to this context.")
+    // without a user there's no telling which language to speak
+    bot.send_message(chat_id, Text::NoUser.localize(Lang::default()))
         .await
 }
 
-fn get_contact_admin_text(user_id: AccountIndex) -> String {
-    format!(
-        "Please contact the system administrator, with your user id {}.",
-        user_id
-    )
+/// Takes the token out of the chat history, or asks the user to when the bot
+/// isn't allowed to.
+async fn delete_token_message(bot: &Bot, msg: &Message, lang: Lang) -> ResponseResult<()> {
+    if let Err(e) = bot.delete_message(msg.chat.id, msg.id).await {
+        eprintln!(
+            "Error while deleting token message, chat id = {}: {}",
+            msg.chat.id, e
+        );
+        bot.send_message(msg.chat.id, Text::DeleteTokenYourself.localize(lang))
+            .await?;
+    }
+    Ok(())
+}
+
+async fn edit_callback_message(
+    bot: &Bot,
+    query: &CallbackQuery,
+    text: String,
+) -> ResponseResult<()> {
+    if let Some(msg) = query.regular_message() {
+        bot.edit_message_text(msg.chat.id, msg.id, text).await?;
+    } else if let Some(ref id) = query.inline_message_id {
+        bot.edit_message_text_inline(id, text).await?;
+    }
+    Ok(())
+}
+
+/// Parses the arguments of /adaptive, two durations like `5m 12h`, or nothing to
+/// turn adaptive polling off.
+fn parse_adaptive_bounds(args: &str) -> Result<Option<AdaptivePolling>, Text> {
+    let bounds: Vec<Duration> = args
+        .split_whitespace()
+        .map(|arg| parse_duration(arg).ok_or(Text::AdaptiveUsage))
+        .collect::<Result<_, _>>()?;
+    match bounds[..] {
+        [] => Ok(None),
+        [min, max] if min > max => Err(Text::AdaptiveBounds),
+        [min, max] => Ok(Some(AdaptivePolling { min, max })),
+        _ => Err(Text::AdaptiveUsage),
+    }
+}
+
+/// Checks a code given back for the pending address, confirming it on a match and
+/// dropping it after too many wrong ones.
+fn confirm_email(email: &mut Option<EmailTarget>, code: &str) -> Text {
+    match email {
+        Some(target) if target.code.as_deref() == Some(code) => {
+            target.code = None;
+            target.attempts = 0;
+            Text::EmailConfirmed(target.address.clone())
+        }
+        Some(target) if !target.is_confirmed() => {
+            target.attempts += 1;
+            if target.attempts < MAX_CODE_ATTEMPTS {
+                return Text::EmailCodeMismatch;
+            }
+            let reply = Text::EmailTooManyCodes(target.address.clone());
+            *email = None;
+            reply
+        }
+        _ => Text::EmailNothingPending,
+    }
 }
 
-fn make_interval_keyboard() -> InlineKeyboardMarkup {
-    let options = vec![1, 3, 5, 10, 30, 60, 120, 360, 1440];
+/// Notes a confirmation code mailed at `now`, unless [`MAX_CODE_SENDS`] went out
+/// within a day before, in which case returns how long until another may. Failed
+/// sends count as well.
+fn note_code_send(sends: &mut Vec<SystemTime>, now: SystemTime) -> Result<(), Duration> {
+    sends.retain(|&at| {
+        now.duration_since(at)
+            .map_or(true, |age| age < CODE_SENDS_WINDOW)
+    });
+    if sends.len() >= MAX_CODE_SENDS {
+        let oldest = sends.iter().min().copied().unwrap_or(now);
+        let wait = (oldest + CODE_SENDS_WINDOW)
+            .duration_since(now)
+            .unwrap_or_default();
+        return Err(wait);
+    }
+    sends.push(now);
+    Ok(())
+}
+
+/// Offers the usual intervals falling within `bounds`.
+fn make_interval_keyboard(lang: Lang, bounds: (Duration, Duration)) -> InlineKeyboardMarkup {
+    let (min, max) = bounds;
+    let options: Vec<_> = INTERVAL_OPTIONS
+        .into_iter()
+        .filter(|&option| (min..=max).contains(&Duration::from_secs(option as u64 * 60)))
+        .collect();
     let mut keys: Vec<Vec<_>> = Vec::new();
     for row in options.chunks(3) {
         keys.push(
             row.iter()
-                .map(|&min| InlineKeyboardButton::callback(format!("{min}min"), min.to_string()))
+                .map(|&min| {
+                    InlineKeyboardButton::callback(
+                        Text::Minutes(min).localize(lang),
+                        Callback::Interval(min).to_string(),
+                    )
+                })
                 .collect(),
         )
     }
-    keys.push(vec![InlineKeyboardButton::callback("Turn Off", "0")]);
+    keys.push(vec![InlineKeyboardButton::callback(
+        Text::TurnOff.localize(lang),
+        Callback::Interval(0).to_string(),
+    )]);
     InlineKeyboardMarkup::new(keys)
 }
+
+fn make_notify_keyboard(lang: Lang) -> InlineKeyboardMarkup {
+    let button = |filter| {
+        InlineKeyboardButton::callback(
+            Text::FilterButton(filter).localize(lang),
+            Callback::Notify(filter).to_string(),
+        )
+    };
+    let mut keys = vec![
+        vec![
+            button(NotificationFilter::All),
+            button(NotificationFilter::StepChanges),
+        ],
+        vec![button(NotificationFilter::Assessments)],
+    ];
+    // from the first step is as good as everything, and sign-up is never reported
+    let steps: Vec<_> = Step::ALL
+        .into_iter()
+        .filter(|step| !matches!(step, Step::CvDeliverance | Step::SignUp))
+        .collect();
+    for row in steps.chunks(2) {
+        keys.push(
+            row.iter()
+                .map(|&step| button(NotificationFilter::From(step)))
+                .collect(),
+        );
+    }
+    InlineKeyboardMarkup::new(keys)
+}
+
+fn make_forgetme_keyboard(lang: Lang) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::callback(
+            Text::EraseEverything.localize(lang),
+            Callback::ForgetMe(true).to_string(),
+        ),
+        InlineKeyboardButton::callback(
+            Text::Cancel.localize(lang),
+            Callback::ForgetMe(false).to_string(),
+        ),
+    ]])
+}
+
+/// Each language is offered in its own name.
+fn make_language_keyboard(lang: Lang) -> InlineKeyboardMarkup {
+    let mut keys: Vec<_> = Lang::ALL
+        .into_iter()
+        .map(|choice| {
+            vec![InlineKeyboardButton::callback(
+                choice.name(),
+                Callback::Language(Some(choice)).to_string(),
+            )]
+        })
+        .collect();
+    keys.push(vec![InlineKeyboardButton::callback(
+        Text::FollowTelegram.localize(lang),
+        Callback::Language(None).to_string(),
+    )]);
+    InlineKeyboardMarkup::new(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::notify::Delivery;
+    use crate::clock::{Clock, ManualClock};
+    use crate::repo::ledger::Retry;
+    use crate::repo::redb::{RedbRepo, RedbSchedule};
+    use crate::repo::schema::tests::{bson_repo, in_memory_db, settings_repo};
+    use crate::repo::schema::{
+        JOBS_TABLE, LEDGER_TABLE, PROGRESS_TABLE, SETTINGS_TABLE, TOKENS_TABLE,
+    };
+    use redb::Database;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+    use tokio::time;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+    /// Nothing listens there, so replies fail fast.
+    const UNREACHABLE_API: &str = "http://127.0.0.1:9";
+
+    /// Passes on the summaries of what it's asked to deliver.
+    struct Recorder(mpsc::UnboundedSender<Vec<String>>);
+
+    impl Notifier for Recorder {
+        fn notify<'a>(
+            &'a self,
+            _account: AccountIndex,
+            changes: &'a [Notification],
+        ) -> Delivery<'a> {
+            let summaries = changes.iter().map(|n| n.summary.clone()).collect();
+            self.0.send(summaries).unwrap();
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    struct Harness {
+        clock: Arc<ManualClock>,
+        wall: SystemTime,
+        schedule: RedbSchedule,
+        settings: RedbRepo<Vec<u8>, UserSettings>,
+        ledger: RedbRepo<Vec<u8>, Ledger>,
+        cache: RedbRepo<Vec<u8>, ApplicationProgress>,
+        tokens: RedbRepo<String, String>,
+        clients: Arc<Mutex<ClientCollection>>,
+        changes: Sender<ScheduleChange<Job>>,
+    }
+
+    impl Harness {
+        /// Starts the monitoring loop over an in-memory database holding the
+        /// given polling intervals and due times.
+        fn start(
+            intervals: &[(AccountIndex, Duration)],
+            due: &[(&str, SystemTime)],
+            maintenance: HashMap<Job, Recurrence>,
+        ) -> Self {
+            Self::start_with_api(UNREACHABLE_API, Vec::new(), intervals, due, maintenance)
+        }
+
+        /// Like [`start`](Self::start), sending replies to the Telegram API at
+        /// `api` and forwarding notifications through `forwarders`.
+        fn start_with_api(
+            api: &str,
+            forwarders: Vec<Arc<dyn Notifier>>,
+            intervals: &[(AccountIndex, Duration)],
+            due: &[(&str, SystemTime)],
+            maintenance: HashMap<Job, Recurrence>,
+        ) -> Self {
+            let wall = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+            let clock = ManualClock::new(wall);
+            let db = Arc::new(in_memory_db());
+            let settings = || settings_repo(db.clone());
+            let mut settings_repo = settings();
+            for &(acc, interval) in intervals {
+                settings_repo
+                    .put(acc, UserSettings::with_interval(interval))
+                    .unwrap();
+            }
+            let cache = || bson_repo(PROGRESS_TABLE, db.clone());
+            let ledger = || bson_repo(LEDGER_TABLE, db.clone());
+            let tokens = || RedbRepo::new(TOKENS_TABLE, db.clone());
+            let mut schedule = RedbSchedule::new(JOBS_TABLE, db.clone());
+            for &(job, at) in due {
+                schedule.set_due(job, at).unwrap();
+            }
+            ensure_tables(&db);
+
+            let (changes, change_rx) = mpsc::channel(1);
+            let clients = Arc::new(Mutex::new(ClientCollection::new()));
+            let mut watch = Watch::new(
+                Arc::new(Bot::new("0:test").set_api_url(reqwest::Url::parse(api).unwrap())),
+                tokens(),
+                clients.clone(),
+                Arc::new(Mutex::new(settings_repo)),
+                Arc::new(Mutex::new(cache())),
+                Arc::new(Mutex::new(ledger())),
+                RedbSchedule::new(JOBS_TABLE, db.clone()),
+                change_rx,
+                WatchConfig {
+                    max_concurrent: 2,
+                    jitter: Jitter::default(),
+                    maintenance,
+                    backup: None,
+                    outbox_max_age: MINUTE * 60,
+                    forwarders,
+                    clock: clock.clone(),
+                },
+            );
+            tokio::spawn(async move { watch.start_monitoring().await });
+            Harness {
+                clock,
+                wall,
+                schedule,
+                settings: settings(),
+                ledger: ledger(),
+                cache: cache(),
+                tokens: tokens(),
+                clients,
+                changes,
+            }
+        }
+
+        /// Waits until the loop and its tasks have nothing left to do but wait,
+        /// which is when paused Tokio time moves on by itself. Only meaningful
+        /// while time is paused.
+        async fn settle(&self) {
+            time::sleep(Duration::from_millis(1)).await;
+        }
+
+        /// Moves the clock on and lets the loop catch up with it.
+        async fn advance(&self, by: Duration) {
+            self.clock.advance(by);
+            self.settle().await;
+        }
+
+        fn due(&self, job: Job) -> Option<SystemTime> {
+            self.schedule.due(&job.to_string()).unwrap()
+        }
+
+        /// Waits for the job to be due at `due`, giving requests to a local server
+        /// the real time they take.
+        async fn await_due(&self, job: Job, due: Option<SystemTime>) {
+            eventually(&format!("{} to be due at {:?}", job, due), || {
+                self.due(job) == due
+            })
+            .await;
+        }
+
+        fn pending(&self, account: AccountIndex) -> Vec<String> {
+            let ledger = self.ledger.get(account).unwrap().unwrap_or_default();
+            ledger.pending().map(|n| n.summary.clone()).collect()
+        }
+
+        /// Whether progress of the account has been stored, which happens even if
+        /// the change is filtered out.
+        fn cached(&self, account: AccountIndex) -> bool {
+            self.cache.get(account).unwrap().is_some()
+        }
+
+        fn retry(&self, account: AccountIndex) -> Option<Retry> {
+            let ledger = self.ledger.get(account).unwrap().unwrap_or_default();
+            ledger.retry()
+        }
+    }
+
+    /// Waits for `condition` to hold while requests to local servers take the real
+    /// time they do, panicking if it still doesn't after ten seconds. Time must not
+    /// be paused, or it would run out while the requests are under way.
+    async fn eventually(what: &str, condition: impl Fn() -> bool) {
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while !condition() {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "Timed out waiting for {}",
+                what
+            );
+            time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// Serves a local endpoint rejecting every token, returning its URL.
+    async fn rejecting_server() -> String {
+        serve("HTTP/1.1 401 Unauthorized\r\ncontent-length: 0\r\n\r\n".into()).await
+    }
+
+    /// An application in the examination step, as the API puts it.
+    const PROGRESS: &str = r#"{
+        "resumeId":1,
+        "currentStatus":{"status":1,"applyProcessType":1},
+        "assessmentInfo":{"status":2,"testAddress":"","mobileTail":""},
+        "positionInfo":{"applyPositionTxt":"Engineer"},
+        "resumeStatus":{"status":3,"isPublic":1},
+        "writtenTestInfo":{"status":0,"itemList":[]},
+        "campusRecruitOne":{"id":1,"itemList":[],"recruitType":1,"typeName":""},
+        "campusRecruitTwo":{"itemList":[],"bgid":1}}"#;
+
+    /// Serves a local endpoint reporting an application at the examination step,
+    /// returning its URL.
+    async fn progress_server() -> String {
+        let body = format!(r#"{{"message":"ok","status":0,"data":{}}}"#, PROGRESS);
+        serve(format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ))
+        .await
+    }
+
+    /// Serves a local Telegram API asking to retry after two minutes, returning its
+    /// URL.
+    async fn flooded_api() -> String {
+        let body = r#"{"ok":false,"error_code":429,"description":"Too Many Requests: retry after 120","parameters":{"retry_after":120}}"#;
+        serve(format!(
+            "HTTP/1.1 429 Too Many Requests\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ))
+        .await
+    }
+
+    /// Answers every request with `response`, returning the URL to reach it.
+    async fn serve(response: String) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request).await;
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        url
+    }
+
+    /// Accepts connections without ever answering, returning the URL to reach it.
+    async fn hanging_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut open = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                open.push(stream);
+            }
+        });
+        url
+    }
+
+    /// Serves a local Telegram API passing on the text of every message sent
+    /// through it, returning its URL.
+    async fn recording_api() -> (String, mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (sent, texts) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                let body = loop {
+                    match stream.read(&mut buffer).await {
+                        Ok(0) | Err(_) => break None,
+                        Ok(read) => request.extend_from_slice(&buffer[..read]),
+                    }
+                    if let Some(body) = request_body(&request) {
+                        break Some(body);
+                    }
+                };
+                if let Some(Ok(json)) = body.map(serde_json::from_slice::<serde_json::Value>) {
+                    let text = json["text"].as_str().unwrap_or_default();
+                    let _ = sent.send(text.to_string());
+                }
+                let body = r#"{"ok":true,"result":{"message_id":1,"date":0,"chat":{"id":1,"type":"private"},"text":""}}"#;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        (url, texts)
+    }
+
+    /// The body of an HTTP request once all of it has arrived.
+    fn request_body(request: &[u8]) -> Option<&[u8]> {
+        let split = request.windows(4).position(|w| w == b"\r\n\r\n")? + 4;
+        let head = String::from_utf8_lossy(&request[..split]).to_ascii_lowercase();
+        let length = head
+            .lines()
+            .find_map(|line| line.strip_prefix("content-length:"))
+            .and_then(|length| length.trim().parse::<usize>().ok())
+            .unwrap_or(0);
+        request.get(split..split + length)
+    }
+
+    fn ensure_tables(db: &Database) {
+        let txn = db.begin_write().unwrap();
+        txn.open_table(PROGRESS_TABLE).unwrap();
+        txn.open_table(SETTINGS_TABLE).unwrap();
+        txn.open_table(JOBS_TABLE).unwrap();
+        txn.open_table(LEDGER_TABLE).unwrap();
+        txn.open_table(TOKENS_TABLE).unwrap();
+        txn.commit().unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn polls_on_interval() {
+        let harness = Harness::start(&[(1, MINUTE * 10)], &[], HashMap::new());
+        harness.settle().await;
+        // never polled before, so polled right away
+        assert_eq!(harness.due(Job::Poll(1)), Some(harness.wall + MINUTE * 10));
+
+        harness.advance(MINUTE * 9).await;
+        assert_eq!(harness.due(Job::Poll(1)), Some(harness.wall + MINUTE * 10));
+        harness.advance(MINUTE).await;
+        assert_eq!(harness.due(Job::Poll(1)), Some(harness.wall + MINUTE * 20));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resumes_persisted_schedule() {
+        let harness = Harness::start(
+            &[(1, MINUTE * 10)],
+            &[(
+                "poll:1",
+                SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000) + MINUTE * 3,
+            )],
+            HashMap::new(),
+        );
+        harness.settle().await;
+        assert_eq!(harness.due(Job::Poll(1)), Some(harness.wall + MINUTE * 3));
+        harness.advance(MINUTE * 3).await;
+        assert_eq!(harness.due(Job::Poll(1)), Some(harness.wall + MINUTE * 13));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn applies_interval_changes() {
+        let harness = Harness::start(&[(1, MINUTE * 10)], &[], HashMap::new());
+        harness.advance(MINUTE * 4).await;
+
+        let job = Job::Poll(1);
+        harness
+            .changes
+            .send(ScheduleChange::Reschedule(job, MINUTE))
+            .await
+            .unwrap();
+        harness.settle().await;
+        assert_eq!(harness.due(job), Some(harness.wall + MINUTE * 5));
+
+        harness
+            .changes
+            .send(ScheduleChange::Remove(job))
+            .await
+            .unwrap();
+        harness.settle().await;
+        assert_eq!(harness.due(job), None);
+        harness.advance(MINUTE * 30).await;
+        assert_eq!(harness.due(job), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refreshes_on_request() {
+        let harness = Harness::start(&[(1, MINUTE * 10)], &[], HashMap::new());
+        harness.advance(MINUTE * 4).await;
+
+        let job = Job::Poll(1);
+        harness
+            .changes
+            .send(ScheduleChange::RunNow(job))
+            .await
+            .unwrap();
+        harness.settle().await;
+        assert_eq!(harness.due(job), Some(harness.wall + MINUTE * 14));
+
+        // too soon after the last one
+        harness.advance(MINUTE / 2).await;
+        harness
+            .changes
+            .send(ScheduleChange::RunNow(job))
+            .await
+            .unwrap();
+        harness.settle().await;
+        assert_eq!(harness.due(job), Some(harness.wall + MINUTE * 14));
+
+        harness.advance(MINUTE).await;
+        harness
+            .changes
+            .send(ScheduleChange::RunNow(job))
+            .await
+            .unwrap();
+        harness.settle().await;
+        assert_eq!(harness.due(job), Some(harness.wall + MINUTE * 31 / 2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn answers_refresh_while_polling() {
+        let (api, mut sent) = recording_api().await;
+        let harness =
+            Harness::start_with_api(&api, Vec::new(), &[(1, MINUTE * 10)], &[], HashMap::new());
+        harness.settle().await;
+        let client = TClient::with_token(&"valid".into()).with_base_url(&hanging_server().await);
+        harness.clients.lock().await.insert(1, client).await;
+
+        // the next poll never hears back, so it stays in flight
+        time::resume();
+        harness.clock.advance(MINUTE * 10);
+        let job = Job::Poll(1);
+        eventually("account 1 to be polled", || {
+            harness.due(job) == Some(harness.wall + MINUTE * 20)
+        })
+        .await;
+        harness
+            .changes
+            .send(ScheduleChange::RunNow(job))
+            .await
+            .unwrap();
+        let reply = time::timeout(Duration::from_secs(10), sent.recv())
+            .await
+            .unwrap();
+        assert_eq!(reply, Some(Text::RefreshRunning.localize(Lang::default())));
+        assert_eq!(harness.due(job), Some(harness.wall + MINUTE * 20));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pauses_on_expired_token() {
+        const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+        let harness = Harness::start(&[(1, MINUTE * 10)], &[], HashMap::new());
+        harness.settle().await;
+        let client =
+            TClient::with_token(&"expired".into()).with_base_url(&rejecting_server().await);
+        harness.clients.lock().await.insert(1, client).await;
+
+        time::resume();
+        harness.clock.advance(MINUTE * 10);
+        let (poll, reminder) = (Job::Poll(1), Job::Reauth(1));
+        harness
+            .await_due(reminder, Some(harness.wall + MINUTE * 10 + DAY))
+            .await;
+        assert_eq!(harness.due(poll), None);
+        time::pause();
+
+        // changing the interval doesn't resume polling
+        harness
+            .changes
+            .send(ScheduleChange::Reschedule(poll, MINUTE))
+            .await
+            .unwrap();
+        harness.settle().await;
+        assert_eq!(harness.due(poll), None);
+
+        harness.advance(DAY).await;
+        assert_eq!(
+            harness.due(reminder),
+            Some(harness.wall + MINUTE * 10 + DAY * 3)
+        );
+
+        // signed in again
+        harness.clients.lock().await.remove(1).await;
+        harness
+            .changes
+            .send(ScheduleChange::Resume(poll))
+            .await
+            .unwrap();
+        harness.settle().await;
+        assert_eq!(harness.due(reminder), None);
+        assert_eq!(harness.due(poll), Some(harness.wall + MINUTE * 20 + DAY));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn records_each_change_once() {
+        let harness = Harness::start(&[(1, MINUTE * 10)], &[], HashMap::new());
+        harness.settle().await;
+        let client = TClient::with_token(&"valid".into()).with_base_url(&progress_server().await);
+        harness.clients.lock().await.insert(1, client).await;
+
+        time::resume();
+        harness.clock.advance(MINUTE * 10);
+        // replies don't get through, so the notification stays pending
+        eventually("the first delivery attempt", || harness.retry(1).is_some()).await;
+        assert_eq!(harness.pending(1), ["Examination"]);
+
+        // the next poll finds nothing new, then tries the pending one again
+        harness.clock.advance(MINUTE * 10);
+        eventually("the second delivery attempt", || {
+            harness.retry(1).is_some_and(|r| r.attempts == 2)
+        })
+        .await;
+        assert_eq!(harness.pending(1), ["Examination"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn leaves_out_filtered_changes() {
+        let mut harness =
+            Harness::start(&[(1, MINUTE * 10), (2, MINUTE * 10)], &[], HashMap::new());
+        for (acc, filter) in [
+            (1, NotificationFilter::From(Step::HrInterview)),
+            (2, NotificationFilter::Assessments),
+        ] {
+            let settings = UserSettings {
+                filter,
+                ..UserSettings::with_interval(MINUTE * 10)
+            };
+            harness.settings.put(acc, settings).unwrap();
+        }
+        harness.settle().await;
+        let api = progress_server().await;
+        for acc in [1, 2] {
+            let client = TClient::with_token(&"valid".into()).with_base_url(&api);
+            harness.clients.lock().await.insert(acc, client).await;
+        }
+
+        // accounts sharing an interval are polled a little apart
+        time::resume();
+        harness.clock.advance(MINUTE * 11);
+        eventually("account 2 to be notified", || {
+            !harness.pending(2).is_empty()
+        })
+        .await;
+        eventually("account 1 to be polled", || harness.cached(1)).await;
+        assert_eq!(harness.pending(2), ["Examination"]);
+        assert!(harness.pending(1).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_undelivered_notifications() {
+        let harness = Harness::start(
+            &[(1, MINUTE * 10)],
+            &[],
+            HashMap::from([(Job::Outbox, Recurrence::Every(MINUTE))]),
+        );
+        harness.settle().await;
+        let client = TClient::with_token(&"valid".into()).with_base_url(&progress_server().await);
+        harness.clients.lock().await.insert(1, client).await;
+
+        time::resume();
+        harness.clock.advance(MINUTE * 10);
+        eventually("the first delivery attempt", || harness.retry(1).is_some()).await;
+        let first = harness.retry(1).unwrap();
+        assert_eq!(first.attempts, 1);
+        assert_eq!(first.at, harness.wall + MINUTE * 21 / 2);
+
+        harness.clock.advance(MINUTE);
+        eventually("the second delivery attempt", || {
+            harness.retry(1).is_some_and(|r| r.attempts == 2)
+        })
+        .await;
+        assert_eq!(harness.retry(1).unwrap().at, harness.wall + MINUTE * 12);
+
+        // given up on once older than the maximum age
+        harness.clock.advance(MINUTE * 60);
+        eventually("the notification to be given up on", || {
+            harness.pending(1).is_empty()
+        })
+        .await;
+        assert_eq!(harness.retry(1), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn respects_retry_after() {
+        let harness = Harness::start_with_api(
+            &flooded_api().await,
+            Vec::new(),
+            &[(1, MINUTE * 10)],
+            &[],
+            HashMap::new(),
+        );
+        harness.settle().await;
+        let client = TClient::with_token(&"valid".into()).with_base_url(&progress_server().await);
+        harness.clients.lock().await.insert(1, client).await;
+
+        time::resume();
+        harness.clock.advance(MINUTE * 10);
+        eventually("the first delivery attempt", || harness.retry(1).is_some()).await;
+        assert_eq!(
+            harness.retry(1).unwrap().at,
+            harness.wall + MINUTE * 10 + Duration::from_secs(120)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn forwards_regardless_of_telegram() {
+        let (tx, mut forwarded) = mpsc::unbounded_channel();
+        let harness = Harness::start_with_api(
+            UNREACHABLE_API,
+            vec![Arc::new(Recorder(tx))],
+            &[(1, MINUTE * 10)],
+            &[],
+            HashMap::new(),
+        );
+        harness.settle().await;
+        let client = TClient::with_token(&"valid".into()).with_base_url(&progress_server().await);
+        harness.clients.lock().await.insert(1, client).await;
+
+        time::resume();
+        harness.clock.advance(MINUTE * 10);
+        let first = time::timeout(Duration::from_secs(10), forwarded.recv())
+            .await
+            .expect("Timed out waiting for the notification to be forwarded");
+        assert_eq!(first, Some(vec!["Examination".to_string()]));
+        eventually("the first delivery attempt", || harness.retry(1).is_some()).await;
+
+        // Telegram is tried again, but the notification was forwarded already
+        harness.clock.advance(MINUTE * 10);
+        eventually("the second delivery attempt", || {
+            harness.retry(1).is_some_and(|r| r.attempts == 2)
+        })
+        .await;
+        assert_eq!(harness.pending(1), ["Examination"]);
+        assert!(forwarded.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stays_paused_across_restarts() {
+        let reminded = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000) + MINUTE * 60;
+        let harness = Harness::start(
+            &[(1, MINUTE * 10), (2, MINUTE * 10)],
+            &[("reauth:1", reminded)],
+            HashMap::new(),
+        );
+        harness.settle().await;
+        assert_eq!(harness.due(Job::Poll(1)), None);
+        assert_eq!(harness.due(Job::Reauth(1)), Some(reminded));
+        assert_eq!(harness.due(Job::Poll(2)), Some(harness.wall + MINUTE * 10));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cleans_up_records_of_signed_out_accounts() {
+        let mut harness = Harness::start(
+            &[],
+            &[],
+            HashMap::from([(Job::Cleanup, Recurrence::Every(MINUTE * 60))]),
+        );
+        let progress: ApplicationProgress = serde_json::from_str(PROGRESS).unwrap();
+        // only the stored token counts, not whether a client is loaded
+        harness.tokens.put(1, "token".to_string()).unwrap();
+        for acc in [1, 2] {
+            harness.cache.put(acc, progress.clone()).unwrap();
+            harness.ledger.put(acc, Ledger::default()).unwrap();
+        }
+        harness.settle().await;
+
+        harness.advance(MINUTE * 60).await;
+        assert!(harness.cached(1));
+        assert!(!harness.cached(2));
+        assert!(harness.ledger.get(1).unwrap().is_some());
+        assert_eq!(harness.ledger.get(2).unwrap(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn runs_maintenance_jobs() {
+        let stale = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000) + MINUTE;
+        let harness = Harness::start(
+            &[],
+            &[("poll:99", stale)],
+            HashMap::from([(Job::Cleanup, Recurrence::Every(MINUTE * 60))]),
+        );
+        harness.settle().await;
+        assert_eq!(harness.due(Job::Cleanup), Some(harness.wall + MINUTE * 60));
+        assert_eq!(harness.due(Job::Poll(99)), Some(stale));
+
+        harness.advance(MINUTE * 60).await;
+        assert_eq!(harness.due(Job::Cleanup), Some(harness.wall + MINUTE * 120));
+        assert_eq!(harness.due(Job::Poll(99)), None);
+        assert_eq!(harness.clock.system_now(), harness.wall + MINUTE * 60);
+    }
+
+    #[test]
+    fn parses_adaptive_bounds_as_durations() {
+        assert_eq!(
+            parse_adaptive_bounds("45m 2h").ok(),
+            Some(Some(AdaptivePolling {
+                min: MINUTE * 45,
+                max: MINUTE * 120
+            }))
+        );
+        assert_eq!(parse_adaptive_bounds("").ok(), Some(None));
+        assert!(matches!(
+            parse_adaptive_bounds("2h 45m"),
+            Err(Text::AdaptiveBounds)
+        ));
+        assert!(matches!(
+            parse_adaptive_bounds("5 720"),
+            Err(Text::AdaptiveUsage)
+        ));
+    }
+
+    #[test]
+    fn caps_confirmation_codes_per_day() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut sends = Vec::new();
+        for i in 0..MAX_CODE_SENDS as u32 {
+            assert_eq!(note_code_send(&mut sends, now + MINUTE * i), Ok(()));
+        }
+        let later = now + MINUTE * 60;
+        assert_eq!(
+            note_code_send(&mut sends, later),
+            Err(CODE_SENDS_WINDOW - MINUTE * 60)
+        );
+        assert_eq!(sends.len(), MAX_CODE_SENDS);
+
+        // the oldest one no longer counts a day later
+        assert_eq!(note_code_send(&mut sends, now + CODE_SENDS_WINDOW), Ok(()));
+        assert_eq!(
+            note_code_send(&mut sends, now + CODE_SENDS_WINDOW),
+            Err(MINUTE)
+        );
+    }
+
+    #[test]
+    fn confirms_email_within_attempts() {
+        let pending = || {
+            Some(EmailTarget {
+                address: "me@example.com".into(),
+                code: Some("042042".into()),
+                attempts: 0,
+            })
+        };
+        let mut email = pending();
+        confirm_email(&mut email, "000000");
+        confirm_email(&mut email, "042042");
+        assert!(email.as_ref().is_some_and(|e| e.is_confirmed()));
+        let reply = confirm_email(&mut email, "042042");
+        assert!(matches!(reply, Text::EmailNothingPending));
+        assert!(email.as_ref().is_some_and(|e| e.is_confirmed()));
+
+        let mut email = pending();
+        for _ in 0..MAX_CODE_ATTEMPTS - 1 {
+            confirm_email(&mut email, "111111");
+        }
+        assert_eq!(
+            email.as_ref().map(|e| e.attempts),
+            Some(MAX_CODE_ATTEMPTS - 1)
+        );
+        confirm_email(&mut email, "111111");
+        assert_eq!(email, None);
+        confirm_email(&mut email, "042042");
+        assert_eq!(email, None);
+    }
+
+    #[test]
+    fn bounds_intervals_per_account() {
+        let limits = IntervalLimits {
+            min: MINUTE * 5,
+            max: MINUTE * 60,
+            caps: HashMap::from([(1, MINUTE * 30)]),
+        };
+        assert_eq!(limits.validate(), Ok(()));
+        assert!(limits.check(0, MINUTE * 5).is_ok());
+        assert!(matches!(
+            limits.check(0, MINUTE),
+            Err(Text::IntervalOutOfRange(min, max)) if min == MINUTE * 5 && max == MINUTE * 60
+        ));
+        assert!(limits.check(0, MINUTE * 61).is_err());
+        assert!(limits.check(1, MINUTE * 10).is_err());
+        assert_eq!(limits.bounds(1), (MINUTE * 30, MINUTE * 60));
+
+        let keyboard = make_interval_keyboard(Lang::En, limits.bounds(0));
+        let offered: Vec<_> = keyboard
+            .inline_keyboard
+            .iter()
+            .flatten()
+            .map(|button| button.text.as_str())
+            .collect();
+        assert_eq!(offered, ["5min", "10min", "30min", "60min", "Turn Off"]);
+    }
+
+    #[test]
+    fn rejects_limits_a_cap_would_widen() {
+        let limits = |min, max, cap| IntervalLimits {
+            min,
+            max,
+            caps: HashMap::from([(1, cap)]),
+        };
+        assert_eq!(
+            limits(MINUTE * 60, MINUTE * 5, MINUTE * 30).validate(),
+            Err(LimitsError::Inverted(MINUTE * 60, MINUTE * 5))
+        );
+        assert_eq!(
+            limits(MINUTE * 5, MINUTE * 60, MINUTE).validate(),
+            Err(LimitsError::CapOutOfRange(1, MINUTE))
+        );
+        assert_eq!(
+            limits(MINUTE * 5, MINUTE * 60, MINUTE * 90).validate(),
+            Err(LimitsError::CapOutOfRange(1, MINUTE * 90))
+        );
+        assert_eq!(limits(MINUTE, MINUTE, MINUTE).validate(), Ok(()));
+    }
+}
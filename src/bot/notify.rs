@@ -0,0 +1,101 @@
+pub mod email;
+pub mod webhook;
+
+use crate::bot::i18n::{Lang, Text};
+use crate::repo::ledger::Notification;
+use crate::repo::model::{AccountIndex, Repository};
+use crate::repo::settings::UserSettings;
+use std::fmt::{Debug, Display, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use teloxide::prelude::{Requester, UserId};
+use teloxide::{Bot, RequestError};
+use tokio::sync::Mutex;
+
+pub type Delivery<'a> = Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>>;
+
+/// A channel notifications reach their users through.
+pub trait Notifier: Send + Sync {
+    /// Delivers the changes to the account's owner as one message.
+    fn notify<'a>(&'a self, account: AccountIndex, changes: &'a [Notification]) -> Delivery<'a>;
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Telegram(RequestError),
+    Http(reqwest::Error),
+    /// The receiving end answered with something other than a success.
+    Status(reqwest::StatusCode),
+    Smtp(lettre::transport::smtp::Error),
+    /// The message couldn't be put together, like with an invalid address.
+    Message(String),
+}
+
+impl Error {
+    /// Time the receiving end asked to wait before trying again, if it did.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::Telegram(RequestError::RetryAfter(after)) => Some(after.duration()),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Telegram(e) => write!(f, "telegram: {}", e),
+            Error::Http(e) => write!(f, "http: {}", e),
+            Error::Status(status) => write!(f, "status {}", status),
+            Error::Smtp(e) => write!(f, "smtp: {}", e),
+            Error::Message(e) => write!(f, "message: {}", e),
+        }
+    }
+}
+
+/// Sends notifications as messages from the bot, in the language of each user.
+pub struct Telegram<S> {
+    bot: Arc<Bot>,
+    settings: Arc<Mutex<S>>,
+}
+
+impl<S> Telegram<S> {
+    pub fn new(bot: Arc<Bot>, settings: Arc<Mutex<S>>) -> Self {
+        Self { bot, settings }
+    }
+}
+
+impl<S> Notifier for Telegram<S>
+where
+    S: Repository<UserSettings> + Send + 'static,
+    <S as Repository<UserSettings>>::Err: Debug,
+{
+    fn notify<'a>(&'a self, account: AccountIndex, changes: &'a [Notification]) -> Delivery<'a> {
+        Box::pin(async move {
+            let text = match changes {
+                [] => return Ok(()),
+                [change] => Text::ProgressUpdate(change.summary.clone()),
+                changes => {
+                    Text::ProgressUpdates(changes.iter().map(|n| n.summary.clone()).collect())
+                }
+            };
+            let lang = match self.settings.lock().await.get(account) {
+                Ok(settings) => Lang::of(&settings.unwrap_or_default()),
+                Err(e) => {
+                    eprintln!(
+                        "Error while reading settings, user id = {}: {:?}",
+                        account, e
+                    );
+                    Lang::default()
+                }
+            };
+            self.bot
+                .send_message(UserId(account), text.localize(lang))
+                .await
+                .map(|_| ())
+                .map_err(Error::Telegram)
+        })
+    }
+}
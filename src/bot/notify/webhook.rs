@@ -0,0 +1,342 @@
+use crate::bot::notify::{Delivery, Error, Notifier};
+use crate::clock::SharedClock;
+use crate::repo::ledger::{Notification, Snapshot};
+use crate::repo::model::{AccountIndex, Repository};
+use crate::repo::settings::{UserSettings, WebhookTarget};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::fmt::Debug;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+/// Header carrying `sha256=` followed by the hex HMAC-SHA256 of the body, keyed
+/// with the user's secret.
+pub const SIGNATURE_HEADER: &str = "X-Qazer-Signature";
+const ATTEMPTS: u32 = 3;
+const FIRST_RETRY: Duration = Duration::from_secs(1);
+/// Longest a single POST may take, so a stalling endpoint can't hold up the
+/// other forwarders.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    account: AccountIndex,
+    changes: Vec<Change<'a>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Change<'a> {
+    id: u64,
+    summary: &'a str,
+    urgent: bool,
+    /// Milliseconds since the Unix epoch.
+    observed_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    progress: Option<&'a Snapshot>,
+}
+
+/// POSTs notifications as JSON to the URL each user set up with /webhook.
+/// Accounts without one are skipped.
+pub struct Webhook<S> {
+    settings: Arc<Mutex<S>>,
+    client: reqwest::Client,
+    clock: SharedClock,
+    first_retry: Duration,
+}
+
+impl<S> Webhook<S> {
+    pub fn new(settings: Arc<Mutex<S>>, clock: SharedClock) -> Self {
+        Self {
+            settings,
+            // a redirect could lead the POST to an address the URL was checked
+            // against when set up
+            client: reqwest::Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("Failed to build webhook client"),
+            clock,
+            first_retry: FIRST_RETRY,
+        }
+    }
+
+    /// Posts the body, retrying on network errors and server side failures with
+    /// doubling waits.
+    async fn post(&self, target: &WebhookTarget, body: Vec<u8>) -> Result<(), Error> {
+        let signature = sign(&target.secret, &body);
+        let mut wait = self.first_retry;
+        let mut attempt = 1;
+        loop {
+            let result = self
+                .client
+                .post(&target.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(SIGNATURE_HEADER, &signature)
+                .body(body.clone())
+                .send()
+                .await;
+            let error = match result {
+                Ok(res) if res.status().is_success() => return Ok(()),
+                Ok(res) => {
+                    let status = res.status();
+                    if !status.is_server_error() && status != reqwest::StatusCode::TOO_MANY_REQUESTS
+                    {
+                        return Err(Error::Status(status));
+                    }
+                    Error::Status(status)
+                }
+                Err(e) => Error::Http(e),
+            };
+            if attempt == ATTEMPTS {
+                return Err(error);
+            }
+            self.clock.sleep_until(self.clock.now() + wait).await;
+            wait *= 2;
+            attempt += 1;
+        }
+    }
+}
+
+impl<S> Notifier for Webhook<S>
+where
+    S: Repository<UserSettings> + Send + 'static,
+    <S as Repository<UserSettings>>::Err: Debug,
+{
+    fn notify<'a>(&'a self, account: AccountIndex, changes: &'a [Notification]) -> Delivery<'a> {
+        Box::pin(async move {
+            let target = match self.settings.lock().await.get(account) {
+                Ok(settings) => settings.and_then(|s| s.webhook),
+                Err(e) => {
+                    eprintln!(
+                        "Error while reading settings, user id = {}: {:?}",
+                        account, e
+                    );
+                    None
+                }
+            };
+            let Some(target) = target else {
+                return Ok(());
+            };
+            let payload = Payload {
+                account,
+                changes: changes
+                    .iter()
+                    .map(|n| Change {
+                        id: n.id,
+                        summary: &n.summary,
+                        urgent: n.urgent,
+                        observed_at: n
+                            .observed
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_millis() as u64,
+                        progress: n.progress.as_ref(),
+                    })
+                    .collect(),
+            };
+            let body = serde_json::to_vec(&payload).expect("Payload is always valid JSON");
+            self.post(&target, body).await
+        })
+    }
+}
+
+/// Whether every address the URL's host resolves to is reachable from the public
+/// internet, so a webhook can't be pointed at the bot's own network.
+pub async fn is_public(url: &reqwest::Url) -> bool {
+    let port = url.port_or_known_default().unwrap_or(443);
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    // IPv6 literals keep their brackets in the URL
+    let literal = host.trim_start_matches('[').trim_end_matches(']');
+    let addresses: Vec<IpAddr> = match literal.parse() {
+        Ok(ip) => vec![ip],
+        Err(_) => match tokio::net::lookup_host((host, port)).await {
+            Ok(addresses) => addresses.map(|address| address.ip()).collect(),
+            Err(_) => return false,
+        },
+    };
+    !addresses.is_empty() && addresses.into_iter().all(is_public_ip)
+}
+
+/// Rules out loopback, private, link-local, unique local and unspecified
+/// addresses, including IPv4 ones mapped into IPv6.
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast())
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ip(ip.into()),
+            None => {
+                let first = ip.segments()[0];
+                let unique_local = first & 0xfe00 == 0xfc00;
+                let link_local = first & 0xffc0 == 0xfe80;
+                !(ip.is_loopback() || ip.is_unspecified() || unique_local || link_local)
+            }
+        },
+    }
+}
+
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(body);
+    let digest = mac.finalize().into_bytes();
+    digest.iter().fold("sha256=".to_string(), |hex, byte| {
+        format!("{}{:02x}", hex, byte)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SystemClock;
+    use crate::repo::schema::tests::{in_memory_db, settings_repo};
+    use crate::tencent::progress::Step;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    /// Accepts requests, failing the first one, and passes on what it received.
+    async fn flaky_listener() -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel(4);
+        tokio::spawn(async move {
+            let mut status = "500 Internal Server Error";
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                // headers and body arrive well within a few reads locally
+                while let Ok(n @ 1..) = stream.read(&mut buf).await {
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length = head
+                            .lines()
+                            .find_map(|l| {
+                                l.to_ascii_lowercase()
+                                    .strip_prefix("content-length: ")
+                                    .map(str::to_owned)
+                            })
+                            .and_then(|l| l.trim().parse::<usize>().ok())
+                            .unwrap_or(0);
+                        if body.len() >= length {
+                            break;
+                        }
+                    }
+                }
+                let response = format!("HTTP/1.1 {}\r\ncontent-length: 0\r\n\r\n", status);
+                let _ = stream.write_all(response.as_bytes()).await;
+                status = "204 No Content";
+                tx.send(String::from_utf8_lossy(&request).into_owned())
+                    .await
+                    .unwrap();
+            }
+        });
+        (url, rx)
+    }
+
+    #[tokio::test]
+    async fn posts_signed_payload_with_retries() {
+        let (url, mut requests) = flaky_listener().await;
+        let mut settings = settings_repo(Arc::new(in_memory_db()));
+        let target = WebhookTarget {
+            url,
+            secret: "hush".into(),
+        };
+        settings
+            .put(
+                1,
+                UserSettings {
+                    webhook: Some(target),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let mut webhook = Webhook::new(Arc::new(Mutex::new(settings)), SystemClock::shared());
+        webhook.first_retry = Duration::from_millis(10);
+
+        let changes = [Notification {
+            id: 7,
+            summary: "Examination".into(),
+            urgent: true,
+            observed: UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+            sent: None,
+            progress: Some(Snapshot {
+                step: Some(Step::Examination),
+                previous_step: Some(Step::CvDeliverance),
+                written_test_status: 0,
+                assessment_status: 2,
+                position: "Engineer".into(),
+            }),
+            forwarded: false,
+        }];
+        webhook.notify(1, &changes).await.unwrap();
+        // accounts without a webhook are skipped
+        webhook.notify(2, &changes).await.unwrap();
+
+        let failed = requests.recv().await.unwrap();
+        let delivered = requests.recv().await.unwrap();
+        assert_eq!(failed, delivered);
+        let (head, body) = delivered.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("POST /hook "));
+        assert_eq!(
+            body,
+            concat!(
+                r#"{"account":1,"changes":[{"id":7,"summary":"Examination","urgent":true,"#,
+                r#""observedAt":1700000000123,"progress":{"step":"examination","#,
+                r#""previousStep":"cvDeliverance","writtenTestStatus":0,"#,
+                r#""assessmentStatus":2,"position":"Engineer"}}]}"#
+            )
+        );
+        let signature = format!(
+            "{}: {}",
+            SIGNATURE_HEADER.to_ascii_lowercase(),
+            sign("hush", body.as_bytes())
+        );
+        assert!(head.to_ascii_lowercase().contains(&signature));
+        assert!(requests.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_non_public_hosts() {
+        for url in [
+            "https://127.0.0.1/hook",
+            "https://localhost/hook",
+            "https://10.0.0.1/hook",
+            "https://192.168.1.1/hook",
+            "https://169.254.169.254/latest",
+            "https://[::1]/hook",
+            "https://[fd00::1]/hook",
+            "https://[fe80::1]/hook",
+            "https://[::ffff:10.0.0.1]/hook",
+        ] {
+            assert!(
+                !is_public(&reqwest::Url::parse(url).unwrap()).await,
+                "{}",
+                url
+            );
+        }
+        assert!(is_public(&reqwest::Url::parse("https://93.184.215.14/hook").unwrap()).await);
+        assert!(is_public(&reqwest::Url::parse("https://[2606:4700::1111]/hook").unwrap()).await);
+    }
+
+    #[test]
+    fn signs_with_hmac_sha256() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use crate::repo::settings::{AdaptivePolling, UserSettings};
+use crate::tencent::progress::Step;
+use std::time::Duration;
+
+/// Polls made at the minimum interval after a change, since more tend to follow.
+const BOOST_POLLS: u32 = 6;
+/// Unchanged polls after which the interval doubles.
+const BACKOFF_AFTER: u32 = 12;
+/// Caps the doublings, which are clamped to the maximum interval anyway.
+const MAX_BACKOFF_SHIFT: u32 = 8;
+
+/// What a poll found, as far as pacing the next ones is concerned.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Observation {
+    Unchanged,
+    Changed(Option<Step>),
+}
+
+/// Per-account state of the adaptive schedule. Kept in memory only, so a restart
+/// begins at the rate of an unknown step.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Pace {
+    step: Option<Step>,
+    unchanged: u32,
+    boost: u32,
+}
+
+impl Pace {
+    pub fn observe(&mut self, observation: Observation) {
+        match observation {
+            Observation::Unchanged => {
+                self.unchanged = self.unchanged.saturating_add(1);
+                self.boost = self.boost.saturating_sub(1);
+            }
+            Observation::Changed(step) => {
+                self.step = step;
+                self.unchanged = 0;
+                self.boost = BOOST_POLLS;
+            }
+        }
+    }
+
+    /// Time until the next poll: the minimum right after a change, otherwise the
+    /// rate of the current step, doubled for every [`BACKOFF_AFTER`] polls without
+    /// a change.
+    pub fn interval(&self, bounds: &AdaptivePolling) -> Duration {
+        if self.boost > 0 {
+            return bounds.min;
+        }
+        let shift = (self.unchanged / BACKOFF_AFTER).min(MAX_BACKOFF_SHIFT);
+        (step_interval(self.step) * (1 << shift)).clamp(bounds.min, bounds.max.max(bounds.min))
+    }
+}
+
+/// Resolves the time until the next poll, or `None` if polling is off.
+pub fn next_interval(settings: &UserSettings, pace: &Pace) -> Option<Duration> {
+    let interval = settings.interval?;
+    Some(match &settings.adaptive {
+        Some(bounds) => pace.interval(bounds),
+        None => interval,
+    })
+}
+
+/// How often an application sitting in `step` is worth checking. Results of
+/// interviews are awaited eagerly, while a delivered CV may go unread for weeks.
+fn step_interval(step: Option<Step>) -> Duration {
+    let minutes = match step {
+        None | Some(Step::CvDeliverance) => 6 * 60,
+        Some(Step::Examination | Step::WrittenTest) => 60,
+        Some(Step::GroupInterview | Step::PreliminaryInterview | Step::SecondaryInterview) => 30,
+        Some(
+            Step::HrInterview
+            | Step::EmployerAssessment
+            | Step::EmployeeConfirmation
+            | Step::OfferConfirmation
+            | Step::SignUp,
+        ) => 15,
+        Some(Step::Completed) => 24 * 60,
+    };
+    Duration::from_secs(minutes * 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn bounds(min: u32, max: u32) -> AdaptivePolling {
+        AdaptivePolling {
+            min: MINUTE * min,
+            max: MINUTE * max,
+        }
+    }
+
+    #[test]
+    fn follows_step_rates() {
+        let bounds = bounds(5, 24 * 60);
+        let mut pace = Pace::default();
+        assert_eq!(pace.interval(&bounds), MINUTE * 6 * 60);
+
+        pace.observe(Observation::Changed(Some(Step::HrInterview)));
+        for _ in 0..BOOST_POLLS {
+            pace.observe(Observation::Unchanged);
+        }
+        assert_eq!(pace.interval(&bounds), MINUTE * 15);
+    }
+
+    #[test]
+    fn speeds_up_after_change() {
+        let bounds = bounds(5, 24 * 60);
+        let mut pace = Pace::default();
+        pace.observe(Observation::Changed(Some(Step::CvDeliverance)));
+        for _ in 1..BOOST_POLLS {
+            pace.observe(Observation::Unchanged);
+            assert_eq!(pace.interval(&bounds), MINUTE * 5);
+        }
+        pace.observe(Observation::Unchanged);
+        assert_eq!(pace.interval(&bounds), MINUTE * 6 * 60);
+    }
+
+    #[test]
+    fn backs_off_within_bounds() {
+        let bounds = bounds(5, 12 * 60);
+        let mut pace = Pace::default();
+        pace.observe(Observation::Changed(Some(Step::WrittenTest)));
+        for _ in 0..BACKOFF_AFTER {
+            pace.observe(Observation::Unchanged);
+        }
+        assert_eq!(pace.interval(&bounds), MINUTE * 120);
+        for _ in 0..BACKOFF_AFTER * 100 {
+            pace.observe(Observation::Unchanged);
+        }
+        assert_eq!(pace.interval(&bounds), MINUTE * 12 * 60);
+    }
+
+    #[test]
+    fn fixed_interval_ignores_pace() {
+        let mut pace = Pace::default();
+        pace.observe(Observation::Changed(None));
+        let fixed = UserSettings::with_interval(MINUTE * 30);
+        assert_eq!(next_interval(&fixed, &pace), Some(MINUTE * 30));
+        let adaptive = UserSettings {
+            adaptive: Some(bounds(1, 60)),
+            ..fixed
+        };
+        assert_eq!(next_interval(&adaptive, &pace), Some(MINUTE));
+        assert_eq!(next_interval(&UserSettings::default(), &pace), None);
+    }
+}
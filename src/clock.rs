@@ -0,0 +1,138 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::time::{self, Instant};
+
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+pub type SharedClock = Arc<dyn Clock>;
+
+/// Source of time for everything that schedules or stamps, so tests can drive it.
+pub trait Clock: Send + Sync {
+    /// Monotonic time, which deadlines are measured in.
+    fn now(&self) -> Instant;
+    /// Wall-clock time, which is what gets persisted and shown.
+    fn system_now(&self) -> SystemTime;
+    fn sleep_until(&self, deadline: Instant) -> Sleep;
+}
+
+/// The clock of the Tokio runtime and the operating system.
+pub struct SystemClock;
+
+impl SystemClock {
+    pub fn shared() -> SharedClock {
+        Arc::new(SystemClock)
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Sleep {
+        Box::pin(time::sleep_until(deadline))
+    }
+}
+
+#[cfg(test)]
+pub use manual::ManualClock;
+
+#[cfg(test)]
+mod manual {
+    use super::{Clock, Sleep};
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll, Waker};
+    use std::time::{Duration, SystemTime};
+    use tokio::time::Instant;
+
+    /// A clock that only moves when told to, waking whoever sleeps past the new
+    /// time.
+    pub struct ManualClock {
+        state: Arc<Mutex<State>>,
+    }
+
+    struct State {
+        start: Instant,
+        wall_start: SystemTime,
+        elapsed: Duration,
+        sleepers: Vec<(Instant, Waker)>,
+    }
+
+    impl State {
+        fn now(&self) -> Instant {
+            self.start + self.elapsed
+        }
+    }
+
+    impl ManualClock {
+        pub fn new(wall: SystemTime) -> Arc<Self> {
+            Arc::new(Self {
+                state: Arc::new(Mutex::new(State {
+                    start: Instant::now(),
+                    wall_start: wall,
+                    elapsed: Duration::ZERO,
+                    sleepers: Vec::new(),
+                })),
+            })
+        }
+
+        pub fn advance(&self, by: Duration) {
+            let mut state = self.state.lock().unwrap();
+            state.elapsed += by;
+            let now = state.now();
+            let (due, pending) = state
+                .sleepers
+                .drain(..)
+                .partition(|(deadline, _)| *deadline <= now);
+            state.sleepers = pending;
+            drop(state);
+            for (_, waker) in due {
+                waker.wake();
+            }
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            self.state.lock().unwrap().now()
+        }
+
+        fn system_now(&self) -> SystemTime {
+            let state = self.state.lock().unwrap();
+            state.wall_start + state.elapsed
+        }
+
+        fn sleep_until(&self, deadline: Instant) -> Sleep {
+            Box::pin(ManualSleep {
+                state: self.state.clone(),
+                deadline,
+            })
+        }
+    }
+
+    struct ManualSleep {
+        state: Arc<Mutex<State>>,
+        deadline: Instant,
+    }
+
+    impl Future for ManualSleep {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            let mut state = self.state.lock().unwrap();
+            if state.now() >= self.deadline {
+                Poll::Ready(())
+            } else {
+                state.sleepers.push((self.deadline, cx.waker().clone()));
+                Poll::Pending
+            }
+        }
+    }
+}
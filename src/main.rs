@@ -1,40 +1,77 @@
-use redb::{Database, TableDefinition};
+use redb::Database;
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
+use teloxide::dispatching::dialogue::{ErasedStorage, Storage};
 use teloxide::dispatching::{HandlerExt, UpdateFilterExt};
-use teloxide::prelude::{
-    CallbackQuery, Dispatcher, LoggingErrorHandler, Requester, ResponseResult,
-};
+use teloxide::prelude::{CallbackQuery, Dispatcher, LoggingErrorHandler, ResponseResult};
 use teloxide::types::{Message, Update};
-use teloxide::utils::command::BotCommands;
 use teloxide::{dptree, Bot};
 use tokio::spawn;
 use tokio::sync::Mutex;
 
 mod bot;
+mod clock;
 mod repo;
 mod tencent;
 mod watch;
 
-use crate::repo::model::AccountIndex;
-use crate::repo::redb::{RedbRepo, RedbRepoDefault};
+use crate::bot::dialogue::{SignInDialogue, State};
+use crate::bot::jobs::{Backup, Job};
+use crate::bot::logic::IntervalLimits;
+use crate::bot::notify::email::{Email, Mailer};
+use crate::bot::notify::webhook::Webhook;
+use crate::bot::notify::Notifier;
+use crate::clock::SystemClock;
+use crate::repo::crypto::{EncryptedRepo, Keyring};
+use crate::repo::ledger::Ledger;
+use crate::repo::redb::{RedbDialogues, RedbRepo, RedbRepoDefault, RedbSchedule};
+use crate::repo::schema::{
+    DIALOGUES_TABLE, JOBS_TABLE, LEDGER_TABLE, PROGRESS_TABLE, SETTINGS_TABLE, TOKENS_TABLE,
+};
+use crate::repo::settings::UserSettings;
 use crate::tencent::model::ApplicationProgress;
+use crate::watch::recurrence::{parse_duration, Recurrence};
+use crate::watch::Jitter;
 use bot::cmd::Command;
 
 type DefaultBasicLogic = bot::logic::Basic<
-    RedbRepoDefault<String>,
+    EncryptedRepo<RedbRepoDefault<String>>,
     RedbRepo<Vec<u8>, ApplicationProgress>,
-    RedbRepo<u32, Duration>,
+    RedbRepo<Vec<u8>, UserSettings>,
+    RedbRepo<Vec<u8>, Ledger>,
+    RedbSchedule,
 >;
 
-const TOKENS_TABLE: TableDefinition<AccountIndex, String> = TableDefinition::new("tokens");
-const PROGRESS_TABLE: TableDefinition<AccountIndex, Vec<u8>> = TableDefinition::new("progress");
-const INTERVAL_TABLE: TableDefinition<AccountIndex, u32> = TableDefinition::new("interval");
+const DEFAULT_MAX_CONCURRENT_POLLS: usize = 8;
+const DEFAULT_OUTBOX_MAX_AGE: Duration = Duration::from_secs(3 * 24 * 60 * 60);
+const DEFAULT_POLL_JITTER: Jitter = Jitter {
+    phase: 0.1,
+    spread: 0.05,
+};
+/// Maintenance jobs, the variables overriding their schedules, and the defaults.
+/// Cron expressions are in UTC.
+const MAINTENANCE_SCHEDULES: [(Job, &str, &str); 5] = [
+    (Job::Digest, "QAZER_DIGEST_SCHEDULE", "off"),
+    (Job::TokenHealth, "QAZER_TOKEN_HEALTH_SCHEDULE", "0 2 * * *"),
+    (Job::Outbox, "QAZER_OUTBOX_SCHEDULE", "1m"),
+    (Job::Backup, "QAZER_BACKUP_SCHEDULE", "0 3 * * *"),
+    (Job::Cleanup, "QAZER_CLEANUP_SCHEDULE", "1d"),
+];
 
 #[tokio::main]
 async fn main() {
+    let keyring = Arc::new(Keyring::from_env().expect("Failed to load token key"));
     let db = Arc::new(Database::create("qazer.redb").expect("Failed to create database"));
-    let token_repo = RedbRepo::new(TOKENS_TABLE, db.to_owned());
+    repo::schema::migrate(&db, &repo::schema::migrations(keyring.to_owned()))
+        .expect("Failed to migrate database");
+    let mut token_repo = EncryptedRepo::new(RedbRepo::new(TOKENS_TABLE, db.to_owned()), keyring.to_owned());
+    match token_repo.rotate().expect("Failed to rotate token key") {
+        0 => {}
+        count => println!("Resealed {} tokens under the primary key", count),
+    }
     let progress_repo = Arc::new(Mutex::new(RedbRepo::new_proxy(
         PROGRESS_TABLE,
         db.to_owned(),
@@ -43,47 +80,94 @@ async fn main() {
             backward: |e| bson::to_vec(&e).unwrap(),
         },
     )));
-    let interval_repo = Arc::new(Mutex::new(RedbRepo::new_proxy(
-        INTERVAL_TABLE,
+    let settings_repo = Arc::new(Mutex::new(RedbRepo::new_proxy(
+        SETTINGS_TABLE,
         db.to_owned(),
         repo::redb::Transformer {
-            forward: |minutes| Duration::from_secs((minutes as u64) * 60),
-            backward: |duration| (duration.as_secs() / 60) as u32,
+            forward: |e| bson::from_slice::<UserSettings>(e.as_slice()).unwrap(),
+            backward: |e| bson::to_vec(&e).unwrap(),
+        },
+    )));
+    let ledger_repo = Arc::new(Mutex::new(RedbRepo::new_proxy(
+        LEDGER_TABLE,
+        db.to_owned(),
+        repo::redb::Transformer {
+            forward: |e| bson::from_slice::<Ledger>(e.as_slice()).unwrap(),
+            backward: |e| bson::to_vec(&e).unwrap(),
         },
     )));
+    let schedule_repo = RedbSchedule::new(JOBS_TABLE, db.to_owned());
+    let backup = env::var("QAZER_BACKUP_PATH").ok().map(|path| Backup {
+        db: db.to_owned(),
+        path: PathBuf::from(path),
+    });
 
     let clients = Arc::new(Mutex::new(bot::clients::ClientCollection::from_token_repo(
         &token_repo,
     )));
     let (ic_tx, ic_rx) = tokio::sync::mpsc::channel(1);
+    let clock = SystemClock::shared();
+
+    let mailer = mailer();
+    let mut forwarders: Vec<Arc<dyn Notifier>> = vec![Arc::new(Webhook::new(
+        settings_repo.to_owned(),
+        clock.to_owned(),
+    ))];
+    if let Some(mailer) = &mailer {
+        forwarders.push(Arc::new(Email::new(settings_repo.to_owned(), mailer.to_owned())));
+    }
 
     let bot = Arc::new(Bot::from_env());
     let mut watch_logic = bot::logic::Watch::new(
         bot.to_owned(),
+        EncryptedRepo::new(RedbRepo::new(TOKENS_TABLE, db.to_owned()), keyring),
         clients.to_owned(),
-        interval_repo.to_owned(),
+        settings_repo.to_owned(),
         progress_repo.to_owned(),
+        ledger_repo.to_owned(),
+        schedule_repo,
         ic_rx,
+        bot::logic::WatchConfig {
+            max_concurrent: max_concurrent_polls(),
+            jitter: poll_jitter(),
+            maintenance: maintenance_schedules(backup.is_some()),
+            backup,
+            outbox_max_age: outbox_max_age(),
+            forwarders,
+            clock: clock.to_owned(),
+        },
     );
-    let basic_logic = Arc::new(Mutex::new(bot::logic::Basic::new(
+    let dialogues: Arc<ErasedStorage<State>> =
+        Arc::new(RedbDialogues::new(DIALOGUES_TABLE, db.to_owned())).erase();
+    let mut basic_logic = bot::logic::Basic::new(
         token_repo,
         progress_repo,
-        interval_repo,
+        settings_repo,
+        ledger_repo,
+        RedbSchedule::new(JOBS_TABLE, db.to_owned()),
         clients,
         ic_tx,
-    )));
+        dialogues.to_owned(),
+    )
+    .with_interval_limits(interval_limits())
+    .with_clock(clock);
+    if let Some(mailer) = mailer {
+        basic_logic = basic_logic.with_mailer(mailer);
+    }
+    let basic_logic = Arc::new(Mutex::new(basic_logic));
 
     let handler = dptree::entry()
         .branch(
             Update::filter_message()
-                .filter_command::<Command>()
-                .endpoint(default_command_handler),
+                .enter_dialogue::<Message, ErasedStorage<State>, State>()
+                .branch(dptree::entry().filter_command::<Command>().endpoint(default_command_handler))
+                .branch(dptree::case![State::ReceiveToken].endpoint(token_handler)),
         )
         .branch(Update::filter_callback_query().endpoint(default_callback_handler));
 
     let watch_handle = spawn(async move { watch_logic.start_monitoring().await });
     Dispatcher::builder(bot.to_owned(), handler)
-        .dependencies(dptree::deps![basic_logic.to_owned()])
+        .dependencies(dptree::deps![basic_logic.to_owned(), dialogues])
         .error_handler(LoggingErrorHandler::new())
         .enable_ctrlc_handler()
         .build()
@@ -92,34 +176,146 @@ async fn main() {
     watch_handle.abort();
 }
 
+/// Read from `QAZER_MAX_CONCURRENT_POLLS`, defaulting to [`DEFAULT_MAX_CONCURRENT_POLLS`].
+fn max_concurrent_polls() -> usize {
+    env::var("QAZER_MAX_CONCURRENT_POLLS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_CONCURRENT_POLLS)
+}
+
+/// Read from `QAZER_OUTBOX_MAX_AGE`, a duration like `12h`, defaulting to
+/// [`DEFAULT_OUTBOX_MAX_AGE`].
+fn outbox_max_age() -> Duration {
+    env::var("QAZER_OUTBOX_MAX_AGE")
+        .ok()
+        .and_then(|value| parse_duration(&value))
+        .unwrap_or(DEFAULT_OUTBOX_MAX_AGE)
+}
+
+/// Read from `QAZER_MIN_INTERVAL` and `QAZER_MAX_INTERVAL`, durations like `5m`,
+/// and `QAZER_INTERVAL_CAPS`, which gives accounts a higher minimum as in
+/// `123456789=10m,987654321=2h`. Panics on limits that don't hold together.
+fn interval_limits() -> IntervalLimits {
+    let duration = |key: &str| env::var(key).ok().and_then(|value| parse_duration(&value));
+    let defaults = IntervalLimits::default();
+    let caps = env::var("QAZER_INTERVAL_CAPS")
+        .unwrap_or_default()
+        .split(',')
+        .filter(|cap| !cap.trim().is_empty())
+        .map(|cap| {
+            cap.split_once('=')
+                .and_then(|(acc, min)| {
+                    Some((acc.trim().parse().ok()?, parse_duration(min)?))
+                })
+                .unwrap_or_else(|| panic!("Invalid interval cap {}", cap))
+        })
+        .collect();
+    let limits = IntervalLimits {
+        min: duration("QAZER_MIN_INTERVAL").unwrap_or(defaults.min),
+        max: duration("QAZER_MAX_INTERVAL").unwrap_or(defaults.max),
+        caps,
+    };
+    if let Err(e) = limits.validate() {
+        panic!("Invalid interval limits: {}", e);
+    }
+    limits
+}
+
+/// Read from `QAZER_SMTP_URL` and `QAZER_SMTP_FROM`. Email is off unless both
+/// are set.
+fn mailer() -> Option<Arc<Mailer>> {
+    let url = env::var("QAZER_SMTP_URL").ok()?;
+    let from = env::var("QAZER_SMTP_FROM").ok()?;
+    match Mailer::from_url(&url, &from) {
+        Ok(mailer) => Some(Arc::new(mailer)),
+        Err(e) => panic!("Invalid SMTP settings: {}", e),
+    }
+}
+
+/// Read from `QAZER_POLL_PHASE` and `QAZER_POLL_JITTER`, both fractions of the
+/// polling interval, defaulting to [`DEFAULT_POLL_JITTER`].
+fn poll_jitter() -> Jitter {
+    let fraction = |key: &str, default: f64| {
+        env::var(key)
+            .ok()
+            .and_then(|value| value.parse::<f64>().ok())
+            .filter(|value| (0.0..=1.0).contains(value))
+            .unwrap_or(default)
+    };
+    Jitter {
+        phase: fraction("QAZER_POLL_PHASE", DEFAULT_POLL_JITTER.phase),
+        spread: fraction("QAZER_POLL_JITTER", DEFAULT_POLL_JITTER.spread),
+    }
+}
+
+/// Reads the schedule of every maintenance job from its variable, where `off`
+/// turns the job off. Backups only run with somewhere to go.
+fn maintenance_schedules(backup: bool) -> HashMap<Job, Recurrence> {
+    MAINTENANCE_SCHEDULES
+        .into_iter()
+        .filter(|&(job, _, _)| backup || job != Job::Backup)
+        .filter_map(|(job, key, default)| {
+            let value = env::var(key).unwrap_or_else(|_| default.to_string());
+            if value.trim() == "off" {
+                return None;
+            }
+            let recurrence = value.parse().unwrap_or_else(|e| {
+                eprintln!("Invalid {}, falling back to {}: {}", key, default, e);
+                default.parse().unwrap()
+            });
+            Some((job, recurrence))
+        })
+        .collect()
+}
+
 async fn default_command_handler(
     bot: Arc<Bot>,
     msg: Message,
     cmd: Command,
     logic: Arc<Mutex<DefaultBasicLogic>>,
+    dialogue: SignInDialogue,
+    state: State,
 ) -> ResponseResult<()> {
+    if state != State::Start && !matches!(cmd, Command::SignIn { .. }) {
+        // any other command calls the sign-in off
+        if let Err(e) = dialogue.exit().await {
+            eprintln!("Error while ending dialogue, chat id = {}: {}", msg.chat.id, e);
+        }
+    }
     match cmd {
         Command::Get => logic.lock().await.get(bot.as_ref(), msg).await?,
-        Command::SignIn { token } => logic.lock().await.signin(bot.as_ref(), msg, token).await?,
+        Command::SignIn { token } => logic.lock().await.signin(bot.as_ref(), msg, token, dialogue).await?,
         Command::SignOut => logic.lock().await.signout(bot.as_ref(), msg).await?,
-        Command::Interval => logic.lock().await.interval(bot.as_ref(), msg).await?,
-        Command::Help => {
-            bot.send_message(msg.chat.id, Command::descriptions().to_string())
-                .await?;
-        }
+        Command::Interval { interval } => logic.lock().await.interval(bot.as_ref(), msg, interval).await?,
+        Command::Refresh => logic.lock().await.refresh(bot.as_ref(), msg).await?,
+        Command::Email { address } => logic.lock().await.email(bot.as_ref(), msg, address).await?,
+        Command::Webhook { url } => logic.lock().await.webhook(bot.as_ref(), msg, url).await?,
+        Command::Notify => logic.lock().await.notify(bot.as_ref(), msg).await?,
+        Command::Quiet { hours } => logic.lock().await.quiet(bot.as_ref(), msg, hours).await?,
+        Command::Adaptive { bounds } => logic.lock().await.adaptive(bot.as_ref(), msg, bounds).await?,
+        Command::Language => logic.lock().await.language(bot.as_ref(), msg).await?,
+        Command::ForgetMe => logic.lock().await.forgetme(bot.as_ref(), msg).await?,
+        Command::Help => logic.lock().await.help(bot.as_ref(), msg).await?,
     };
     Ok(())
 }
 
+async fn token_handler(
+    bot: Arc<Bot>,
+    msg: Message,
+    logic: Arc<Mutex<DefaultBasicLogic>>,
+    dialogue: SignInDialogue,
+) -> ResponseResult<()> {
+    logic.lock().await.receive_token(bot.as_ref(), msg, dialogue).await?;
+    Ok(())
+}
+
 async fn default_callback_handler(
     bot: Arc<Bot>,
     q: CallbackQuery,
     logic: Arc<Mutex<DefaultBasicLogic>>,
 ) -> ResponseResult<()> {
-    logic
-        .lock()
-        .await
-        .interval_callback_handler(bot.as_ref(), q)
-        .await?;
+    logic.lock().await.callback_handler(bot.as_ref(), q).await?;
     Ok(())
 }
@@ -1,2 +1,6 @@
+pub mod crypto;
+pub mod ledger;
 pub mod model;
-pub mod redb;
\ No newline at end of file
+pub mod redb;
+pub mod schema;
+pub mod settings;
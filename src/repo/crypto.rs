@@ -0,0 +1,408 @@
+use crate::repo::model::{AccountIndex, Repository, TransactionalRepository};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
+
+pub const KEY_ENV: &str = "QAZER_TOKEN_KEY";
+pub const KEY_FILE_ENV: &str = "QAZER_TOKEN_KEY_FILE";
+pub const RETIRED_KEYS_ENV: &str = "QAZER_TOKEN_RETIRED_KEYS";
+
+const SEALED_PREFIX: &str = "enc:v1:";
+const NONCE_LEN: usize = 24;
+
+#[derive(Debug)]
+pub enum Error {
+    MissingKey,
+    InvalidKey(String),
+    UnknownKey(String),
+    Malformed,
+    Plaintext,
+    Decryption,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::MissingKey => write!(
+                f,
+                "no token key configured, set {} or {}",
+                KEY_ENV, KEY_FILE_ENV
+            ),
+            Error::InvalidKey(reason) => write!(f, "invalid token key: {}", reason),
+            Error::UnknownKey(id) => write!(f, "token sealed under unknown key {}", id),
+            Error::Malformed => write!(f, "malformed sealed token"),
+            Error::Plaintext => write!(f, "token is stored in plaintext"),
+            Error::Decryption => write!(f, "token failed authentication"),
+        }
+    }
+}
+
+struct Key {
+    id: String,
+    cipher: XChaCha20Poly1305,
+}
+
+impl Key {
+    fn parse(encoded: &str) -> Result<Self, Error> {
+        let bytes = BASE64_STANDARD
+            .decode(encoded.trim())
+            .map_err(|e| Error::InvalidKey(e.to_string()))?;
+        if bytes.len() != 32 {
+            return Err(Error::InvalidKey(format!(
+                "expected 32 bytes, got {}",
+                bytes.len()
+            )));
+        }
+        let digest = Sha256::digest(&bytes);
+        let id = digest[..4].iter().map(|b| format!("{:02x}", b)).collect();
+        let cipher = XChaCha20Poly1305::new_from_slice(&bytes)
+            .map_err(|e| Error::InvalidKey(e.to_string()))?;
+        Ok(Self { id, cipher })
+    }
+}
+
+/// The set of keys tokens are sealed with. New tokens are always sealed under the
+/// primary key, while retired keys are only kept around to open older rows until
+/// they get resealed.
+pub struct Keyring {
+    primary: Key,
+    retired: Vec<Key>,
+}
+
+impl Keyring {
+    pub fn new<'a>(
+        primary: &str,
+        retired: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            primary: Key::parse(primary)?,
+            retired: retired
+                .into_iter()
+                .map(Key::parse)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Reads the keys from the file named by [`KEY_FILE_ENV`], one base64 key per
+    /// line with the primary key first, or otherwise from [`KEY_ENV`] and the
+    /// comma separated [`RETIRED_KEYS_ENV`].
+    pub fn from_env() -> Result<Self, Error> {
+        if let Ok(path) = env::var(KEY_FILE_ENV) {
+            let content = std::fs::read_to_string(&path)
+                .map_err(|e| Error::InvalidKey(format!("{}: {}", path, e)))?;
+            let mut keys = content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'));
+            let primary = keys.next().ok_or(Error::MissingKey)?;
+            return Self::new(primary, keys);
+        }
+
+        let primary = env::var(KEY_ENV).map_err(|_| Error::MissingKey)?;
+        let retired = env::var(RETIRED_KEYS_ENV).unwrap_or_default();
+        Self::new(
+            &primary,
+            retired.split(',').filter(|key| !key.trim().is_empty()),
+        )
+    }
+
+    /// Encrypts a token under the primary key. The account index is authenticated
+    /// along with it, so a sealed token can't be moved to another account's row.
+    pub fn seal(&self, account: AccountIndex, token: &str) -> String {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let aad = account.to_be_bytes();
+        let ciphertext = self
+            .primary
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: token.as_bytes(),
+                    aad: &aad,
+                },
+            )
+            .expect("Failed to encrypt token");
+        let mut payload = nonce.to_vec();
+        payload.extend(ciphertext);
+        format!(
+            "{}{}:{}",
+            SEALED_PREFIX,
+            self.primary.id,
+            BASE64_STANDARD.encode(payload)
+        )
+    }
+
+    pub fn open(&self, account: AccountIndex, sealed: &str) -> Result<String, Error> {
+        let (key_id, payload) = split_sealed(sealed)?;
+        let key = self.find(key_id)?;
+        let payload = BASE64_STANDARD
+            .decode(payload)
+            .map_err(|_| Error::Malformed)?;
+        if payload.len() < NONCE_LEN {
+            return Err(Error::Malformed);
+        }
+        let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+        let aad = account.to_be_bytes();
+        let plaintext = key
+            .cipher
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| Error::Decryption)?;
+        String::from_utf8(plaintext).map_err(|_| Error::Malformed)
+    }
+
+    /// Whether the value is sealed under the primary key, i.e. needs no rotation.
+    pub fn is_current(&self, sealed: &str) -> bool {
+        split_sealed(sealed).is_ok_and(|(id, _)| id == self.primary.id)
+    }
+
+    pub fn is_sealed(value: &str) -> bool {
+        value.starts_with(SEALED_PREFIX)
+    }
+
+    fn find(&self, id: &str) -> Result<&Key, Error> {
+        std::iter::once(&self.primary)
+            .chain(&self.retired)
+            .find(|key| key.id == id)
+            .ok_or_else(|| Error::UnknownKey(id.to_string()))
+    }
+}
+
+fn split_sealed(sealed: &str) -> Result<(&str, &str), Error> {
+    sealed
+        .strip_prefix(SEALED_PREFIX)
+        .ok_or(Error::Plaintext)?
+        .split_once(':')
+        .ok_or(Error::Malformed)
+}
+
+#[derive(Debug)]
+pub enum RepoError<E> {
+    Inner(E),
+    Cipher(Error),
+}
+
+impl<E: Display> Display for RepoError<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RepoError::Inner(e) => write!(f, "{}", e),
+            RepoError::Cipher(e) => write!(f, "cipher: {}", e),
+        }
+    }
+}
+
+/// Seals tokens before handing them to the inner repository, and opens them on the
+/// way back, so callers keep working with plain `String` tokens.
+pub struct EncryptedRepo<R> {
+    inner: R,
+    keyring: Arc<Keyring>,
+}
+
+impl<R> EncryptedRepo<R>
+where
+    R: Repository<String>,
+{
+    pub fn new(inner: R, keyring: Arc<Keyring>) -> Self {
+        Self { inner, keyring }
+    }
+
+    /// Reseals every row that isn't sealed under the primary key, returning how
+    /// many rows were rewritten.
+    pub fn rotate(&mut self) -> Result<usize, RepoError<R::Err>> {
+        let stale: Vec<_> = self
+            .inner
+            .entries()
+            .map_err(RepoError::Inner)?
+            .filter(|(_, sealed)| !self.keyring.is_current(sealed))
+            .collect();
+        for (account, sealed) in &stale {
+            let token = self
+                .keyring
+                .open(*account, sealed)
+                .map_err(RepoError::Cipher)?;
+            self.inner
+                .put(*account, self.keyring.seal(*account, &token))
+                .map_err(RepoError::Inner)?;
+        }
+        Ok(stale.len())
+    }
+}
+
+impl<R> Repository<String> for EncryptedRepo<R>
+where
+    R: Repository<String>,
+    R::Err: Debug,
+{
+    type Err = RepoError<R::Err>;
+
+    fn get(&self, account: AccountIndex) -> Result<Option<String>, Self::Err> {
+        self.inner
+            .get(account)
+            .map_err(RepoError::Inner)?
+            .map(|sealed| self.keyring.open(account, &sealed))
+            .transpose()
+            .map_err(RepoError::Cipher)
+    }
+
+    fn revoke(&mut self, account: AccountIndex) -> Result<Option<String>, Self::Err> {
+        self.inner
+            .revoke(account)
+            .map_err(RepoError::Inner)?
+            .map(|sealed| self.keyring.open(account, &sealed))
+            .transpose()
+            .map_err(RepoError::Cipher)
+    }
+
+    fn put(&mut self, account: AccountIndex, data: String) -> Result<(), Self::Err> {
+        self.inner
+            .put(account, self.keyring.seal(account, &data))
+            .map_err(RepoError::Inner)
+    }
+
+    fn keys(&self) -> Result<impl Iterator<Item = AccountIndex>, Self::Err> {
+        self.inner.keys().map_err(RepoError::Inner)
+    }
+
+    fn entries(&self) -> Result<impl Iterator<Item = (AccountIndex, String)>, Self::Err> {
+        // A row that doesn't open means the keyring is wrong or the row is corrupt.
+        // Failing loudly keeps callers from mistaking those accounts for signed out.
+        let opened = self
+            .inner
+            .entries()
+            .map_err(RepoError::Inner)?
+            .map(|(account, sealed)| {
+                self.keyring
+                    .open(account, &sealed)
+                    .map(|token| (account, token))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(RepoError::Cipher)?;
+        Ok(opened.into_iter())
+    }
+}
+
+impl<R> TransactionalRepository<String> for EncryptedRepo<R>
+where
+    R: TransactionalRepository<String>,
+    R::Err: Debug,
+{
+    type Unit = R::Unit;
+
+    fn begin(&self) -> Result<R::Unit, Self::Err> {
+        self.inner.begin().map_err(RepoError::Inner)
+    }
+
+    fn put_in(&self, unit: &R::Unit, account: AccountIndex, data: String) -> Result<(), Self::Err> {
+        self.inner
+            .put_in(unit, account, self.keyring.seal(account, &data))
+            .map_err(RepoError::Inner)
+    }
+
+    fn revoke_in(
+        &self,
+        unit: &R::Unit,
+        account: AccountIndex,
+    ) -> Result<Option<String>, Self::Err> {
+        self.inner
+            .revoke_in(unit, account)
+            .map_err(RepoError::Inner)?
+            .map(|sealed| self.keyring.open(account, &sealed))
+            .transpose()
+            .map_err(RepoError::Cipher)
+    }
+
+    fn contains_in(&self, unit: &R::Unit, account: AccountIndex) -> Result<bool, Self::Err> {
+        self.inner
+            .contains_in(unit, account)
+            .map_err(RepoError::Inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::redb::RedbRepo;
+    use crate::repo::schema::tests::in_memory_db;
+    use crate::repo::schema::TOKENS_TABLE;
+
+    const KEY_A: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+    const KEY_B: &str = "Hx4dHBsaGRgXFhUUExIREA8ODQwLCgkIBwYFBAMCAQA=";
+
+    #[test]
+    fn seals_and_opens() {
+        let keyring = Keyring::new(KEY_A, []).unwrap();
+        let sealed = keyring.seal(42, "cookie");
+        assert!(Keyring::is_sealed(&sealed));
+        assert!(!sealed.contains("cookie"));
+        assert_eq!(keyring.open(42, &sealed).unwrap(), "cookie");
+    }
+
+    #[test]
+    fn binds_token_to_account() {
+        let keyring = Keyring::new(KEY_A, []).unwrap();
+        let sealed = keyring.seal(42, "cookie");
+        assert!(matches!(keyring.open(43, &sealed), Err(Error::Decryption)));
+    }
+
+    #[test]
+    fn rejects_plaintext_and_unknown_keys() {
+        let old = Keyring::new(KEY_A, []).unwrap();
+        let new = Keyring::new(KEY_B, []).unwrap();
+        assert!(matches!(new.open(1, "cookie"), Err(Error::Plaintext)));
+        assert!(matches!(
+            new.open(1, &old.seal(1, "cookie")),
+            Err(Error::UnknownKey(_))
+        ));
+    }
+
+    #[test]
+    fn rotates_to_primary_key() {
+        let old = Arc::new(Keyring::new(KEY_A, []).unwrap());
+        let rotated = Arc::new(Keyring::new(KEY_B, [KEY_A]).unwrap());
+        let db = Arc::new(in_memory_db());
+
+        let mut repo = EncryptedRepo::new(RedbRepo::new(TOKENS_TABLE, db.clone()), old);
+        repo.put(1, "one".to_string()).unwrap();
+        repo.put(2, "two".to_string()).unwrap();
+
+        let mut repo = EncryptedRepo::new(RedbRepo::new(TOKENS_TABLE, db.clone()), rotated);
+        assert_eq!(repo.get(1).unwrap().as_deref(), Some("one"));
+        assert_eq!(repo.rotate().unwrap(), 2);
+        assert_eq!(repo.rotate().unwrap(), 0);
+
+        let only_new = Arc::new(Keyring::new(KEY_B, []).unwrap());
+        let repo = EncryptedRepo::new(RedbRepo::new(TOKENS_TABLE, db), only_new);
+        let mut entries: Vec<_> = repo.entries().unwrap().collect();
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec![(1, "one".to_string()), (2, "two".to_string())]
+        );
+    }
+
+    #[test]
+    fn entries_fail_on_unopenable_rows() {
+        let db = Arc::new(in_memory_db());
+        let mut repo = EncryptedRepo::new(
+            RedbRepo::new(TOKENS_TABLE, db.clone()),
+            Arc::new(Keyring::new(KEY_A, []).unwrap()),
+        );
+        repo.put(1, "one".to_string()).unwrap();
+
+        let wrong_key = Arc::new(Keyring::new(KEY_B, []).unwrap());
+        let repo = EncryptedRepo::new(RedbRepo::new(TOKENS_TABLE, db), wrong_key);
+        assert!(matches!(
+            repo.entries().map(|entries| entries.count()),
+            Err(RepoError::Cipher(Error::UnknownKey(_)))
+        ));
+    }
+}
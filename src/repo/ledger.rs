@@ -0,0 +1,249 @@
+use crate::tencent::progress::Step;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
+
+/// Sent notifications kept per account, besides the pending ones.
+const KEPT_SENT: usize = 20;
+/// Wait before retrying a failed delivery the first time.
+const FIRST_RETRY: Duration = Duration::from_secs(30);
+/// Longest wait between two attempts to deliver.
+const MAX_RETRY_GAP: Duration = Duration::from_secs(60 * 60);
+
+/// Notifications of one account, recorded along with the progress that caused
+/// them, so each change is announced once no matter how often it's polled or
+/// whether the bot restarts in between.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Ledger {
+    next_id: u64,
+    entries: Vec<Notification>,
+    /// Set while delivering the pending notifications fails.
+    retry: Option<Retry>,
+    /// When the progress last changed, whether or not that was notified of.
+    changed: Option<SystemTime>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Retry {
+    /// Failed attempts in a row.
+    pub attempts: u32,
+    /// Time before which the next attempt isn't made.
+    pub at: SystemTime,
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Notification {
+    pub id: u64,
+    pub summary: String,
+    /// Whether it comes through quiet hours that let urgent notifications in.
+    pub urgent: bool,
+    pub observed: SystemTime,
+    /// When it was sent, or `None` while it's pending.
+    pub sent: Option<SystemTime>,
+    /// `None` for notifications recorded before snapshots were kept.
+    #[serde(default)]
+    pub progress: Option<Snapshot>,
+    /// Whether it was handed to the channels besides Telegram, which don't wait
+    /// for Telegram to get through.
+    #[serde(default)]
+    pub forwarded: bool,
+}
+
+/// Where the application stood when a notification was recorded, for channels
+/// passing it on as data rather than as text.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Snapshot {
+    pub step: Option<Step>,
+    /// Step of the progress replaced, or `None` if it wasn't known or had none.
+    pub previous_step: Option<Step>,
+    pub written_test_status: u8,
+    pub assessment_status: u8,
+    pub position: String,
+}
+
+impl Ledger {
+    /// Adds a pending notification, returning its id.
+    pub fn record(
+        &mut self,
+        summary: String,
+        urgent: bool,
+        progress: Snapshot,
+        at: SystemTime,
+    ) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.push(Notification {
+            id,
+            summary,
+            urgent,
+            observed: at,
+            sent: None,
+            progress: Some(progress),
+            forwarded: false,
+        });
+        id
+    }
+
+    /// Notifications not sent yet, oldest first.
+    pub fn pending(&self) -> impl Iterator<Item = &Notification> {
+        self.entries.iter().filter(|n| n.sent.is_none())
+    }
+
+    pub fn retry(&self) -> Option<Retry> {
+        self.retry
+    }
+
+    pub fn changed(&self) -> Option<SystemTime> {
+        self.changed
+    }
+
+    pub fn note_change(&mut self, at: SystemTime) {
+        self.changed = Some(at);
+    }
+
+    /// Notes a failed attempt to deliver at `at`, returning when to try again. The
+    /// wait doubles with every attempt, unless the server asked for one.
+    pub fn fail(&mut self, at: SystemTime, retry_after: Option<Duration>) -> SystemTime {
+        let attempts = self.retry.map_or(0, |r| r.attempts) + 1;
+        let backoff = FIRST_RETRY
+            .saturating_mul(1 << (attempts - 1).min(16))
+            .min(MAX_RETRY_GAP);
+        let retry = Retry {
+            attempts,
+            at: at + retry_after.unwrap_or(backoff),
+        };
+        self.retry = Some(retry);
+        retry.at
+    }
+
+    /// Drops the pending notifications observed before `before`, returning how
+    /// many there were.
+    pub fn expire(&mut self, before: SystemTime) -> usize {
+        let count = self.entries.len();
+        self.entries
+            .retain(|n| n.sent.is_some() || n.observed >= before);
+        if self.pending().next().is_none() {
+            self.retry = None;
+        }
+        count - self.entries.len()
+    }
+
+    pub fn mark_forwarded(&mut self, ids: &[u64]) {
+        for entry in &mut self.entries {
+            if ids.contains(&entry.id) {
+                entry.forwarded = true;
+            }
+        }
+    }
+
+    /// Marks the notifications as sent at `at`, dropping the oldest sent ones
+    /// beyond what's kept for reference. Retrying starts over.
+    pub fn mark_sent(&mut self, ids: &[u64], at: SystemTime) {
+        self.retry = None;
+        for entry in &mut self.entries {
+            if ids.contains(&entry.id) {
+                entry.sent.get_or_insert(at);
+            }
+        }
+        let mut excess = self
+            .entries
+            .iter()
+            .filter(|n| n.sent.is_some())
+            .count()
+            .saturating_sub(KEPT_SENT);
+        self.entries.retain(|n| {
+            let drop = excess > 0 && n.sent.is_some();
+            excess -= drop as usize;
+            !drop
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn sends_each_notification_once() {
+        let at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut ledger = Ledger::default();
+        let first = ledger.record("Examination".into(), true, Snapshot::default(), at);
+        let second = ledger.record("Interview".into(), false, Snapshot::default(), at);
+        assert_eq!(ledger.pending().count(), 2);
+
+        ledger.mark_sent(&[first], at);
+        let pending: Vec<_> = ledger.pending().map(|n| n.id).collect();
+        assert_eq!(pending, [second]);
+        ledger.mark_sent(&[first, second], at + Duration::from_secs(60));
+        assert_eq!(ledger.pending().count(), 0);
+        assert_eq!(ledger.entries[0].sent, Some(at));
+
+        let encoded = bson::to_vec(&ledger).unwrap();
+        assert_eq!(bson::from_slice::<Ledger>(&encoded).unwrap(), ledger);
+    }
+
+    #[test]
+    fn keeps_recent_history() {
+        let at = UNIX_EPOCH;
+        let mut ledger = Ledger::default();
+        let ids: Vec<_> = (0..30)
+            .map(|i| ledger.record(i.to_string(), false, Snapshot::default(), at))
+            .collect();
+        let pending = ledger.record("pending".into(), false, Snapshot::default(), at);
+        ledger.mark_sent(&ids, at);
+        assert_eq!(ledger.entries.len(), KEPT_SENT + 1);
+        assert_eq!(ledger.entries[0].id, 10);
+        assert_eq!(
+            ledger.pending().map(|n| n.id).collect::<Vec<_>>(),
+            [pending]
+        );
+    }
+
+    #[test]
+    fn backs_off_until_delivered() {
+        let at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut ledger = Ledger::default();
+        let id = ledger.record("Examination".into(), true, Snapshot::default(), at);
+        assert_eq!(ledger.fail(at, None), at + FIRST_RETRY);
+        assert_eq!(ledger.fail(at, None), at + FIRST_RETRY * 2);
+        assert_eq!(ledger.fail(at, None), at + FIRST_RETRY * 4);
+        let asked = Duration::from_secs(7);
+        assert_eq!(ledger.fail(at, Some(asked)), at + asked);
+        assert_eq!(ledger.retry().map(|r| r.attempts), Some(4));
+        for _ in 0..40 {
+            ledger.fail(at, None);
+        }
+        assert_eq!(ledger.retry().map(|r| r.at), Some(at + MAX_RETRY_GAP));
+
+        ledger.mark_sent(&[id], at);
+        assert_eq!(ledger.retry(), None);
+    }
+
+    #[test]
+    fn expires_old_pending_notifications() {
+        let at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let hour = Duration::from_secs(60 * 60);
+        let mut ledger = Ledger::default();
+        let sent = ledger.record("CvDeliverance".into(), false, Snapshot::default(), at);
+        ledger.mark_sent(&[sent], at);
+        ledger.record("Examination".into(), false, Snapshot::default(), at);
+        let recent = ledger.record(
+            "Interview".into(),
+            false,
+            Snapshot::default(),
+            at + hour * 2,
+        );
+        ledger.fail(at + hour * 2, None);
+
+        assert_eq!(ledger.expire(at + hour), 1);
+        assert_eq!(ledger.pending().map(|n| n.id).collect::<Vec<_>>(), [recent]);
+        assert!(ledger.retry().is_some());
+        assert_eq!(ledger.expire(at + hour * 3), 1);
+        assert_eq!(ledger.retry(), None);
+        assert_eq!(ledger.entries.len(), 1);
+    }
+}
@@ -1,10 +1,38 @@
+use std::time::SystemTime;
+
 pub type AccountIndex = u64;
 
 pub trait Repository<T> {
     type Err;
     fn get(&self, account: AccountIndex) -> Result<Option<T>, Self::Err>;
+    // Everything in the bot revokes through a unit of work by now.
+    #[allow(dead_code)]
     fn revoke(&mut self, account: AccountIndex) -> Result<Option<T>, Self::Err>;
     fn put(&mut self, account: AccountIndex, data: T) -> Result<(), Self::Err>;
     fn keys(&self) -> Result<impl Iterator<Item = AccountIndex>, Self::Err>;
     fn entries(&self) -> Result<impl Iterator<Item = (AccountIndex, T)>, Self::Err>;
 }
+
+/// A set of changes, possibly spanning several repositories, that takes effect
+/// all at once on [`commit`](UnitOfWork::commit) or not at all if dropped.
+pub trait UnitOfWork {
+    type Err;
+    fn commit(self) -> Result<(), Self::Err>;
+}
+
+pub trait TransactionalRepository<T>: Repository<T> {
+    type Unit: UnitOfWork;
+    fn begin(&self) -> Result<Self::Unit, Self::Err>;
+    fn put_in(&self, unit: &Self::Unit, account: AccountIndex, data: T) -> Result<(), Self::Err>;
+    fn revoke_in(&self, unit: &Self::Unit, account: AccountIndex) -> Result<Option<T>, Self::Err>;
+    fn contains_in(&self, unit: &Self::Unit, account: AccountIndex) -> Result<bool, Self::Err>;
+}
+
+/// When each scheduled job is due next, keyed by the job's name.
+pub trait ScheduleRepository {
+    type Err;
+    fn due(&self, job: &str) -> Result<Option<SystemTime>, Self::Err>;
+    fn set_due(&mut self, job: &str, due: SystemTime) -> Result<(), Self::Err>;
+    fn clear(&mut self, job: &str) -> Result<(), Self::Err>;
+    fn jobs(&self) -> Result<Vec<String>, Self::Err>;
+}
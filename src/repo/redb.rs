@@ -1,11 +1,20 @@
-use crate::repo::model::{AccountIndex, Repository};
+use crate::repo::model::{
+    AccountIndex, Repository, ScheduleRepository, TransactionalRepository, UnitOfWork,
+};
 use redb::{
-    Database, DatabaseError, Error, ReadOnlyTable, ReadableTable, TableDefinition,
-    TableError, Value,
+    Database, Error, ReadOnlyTable, ReadableTable, TableDefinition,
+    TableError, Value, WriteTransaction,
 };
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use std::convert::Into;
-use std::path::Path;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use teloxide::dispatching::dialogue::Storage;
+use teloxide::types::ChatId;
 
 type AccountIndexedTable<T> = TableDefinition<'static, AccountIndex, T>;
 pub type RedbRepoDefault<T> = RedbRepo<T, T>;
@@ -35,13 +44,6 @@ where
             },
         }
     }
-
-    pub fn create(
-        table: AccountIndexedTable<T>,
-        name: impl AsRef<Path>,
-    ) -> Result<Self, DatabaseError> {
-        Ok(Self::new(table, Arc::new(Database::create(name)?)))
-    }
 }
 
 impl<From, Into> RedbRepo<From, Into>
@@ -59,18 +61,6 @@ where
             transform,
         }
     }
-
-    pub fn create_proxy(
-        table: AccountIndexedTable<From>,
-        name: impl AsRef<Path>,
-        transform: Transformer<From, Into>,
-    ) -> Result<Self, DatabaseError> {
-        Ok(Self::new_proxy(
-            table,
-            Arc::new(Database::create(name)?),
-            transform,
-        ))
-    }
 }
 
 impl<From, Into> Repository<Into> for RedbRepo<From, Into>
@@ -89,20 +79,16 @@ where
     }
 
     fn revoke(&mut self, account: AccountIndex) -> Result<Option<Into>, Error> {
-        let write_txn = self.db.begin_write()?;
-        let mut table = write_txn.open_table(self.table)?;
-        let option = table.remove(account)?;
-        Ok(option.map(|s| (self.transform.forward)(s.value().clone())))
+        let unit = self.begin()?;
+        let option = self.revoke_in(&unit, account)?;
+        unit.commit()?;
+        Ok(option)
     }
 
     fn put(&mut self, account: AccountIndex, data: Into) -> Result<(), Error> {
-        let write_txn = self.db.begin_write()?;
-        {
-            let mut table = write_txn.open_table(self.table)?;
-            table.insert(account, (self.transform.backward)(data))?;
-        }
-        write_txn.commit()?;
-        Ok(())
+        let unit = self.begin()?;
+        self.put_in(&unit, account, data)?;
+        unit.commit()
     }
 
     fn keys(&self) -> Result<impl Iterator<Item = AccountIndex>, Error> {
@@ -137,6 +123,53 @@ where
     }
 }
 
+/// Repositories joining the same unit must live in the same database as the one
+/// that began it.
+impl<From, Into> TransactionalRepository<Into> for RedbRepo<From, Into>
+where
+    for<'a> From: Value<SelfType<'a> = From> + Clone + 'static + std::borrow::Borrow<<From as Value>::SelfType<'a>>,
+    for<'a> <From as Value>::SelfType<'a>: Clone
+{
+    type Unit = RedbUnit;
+
+    fn begin(&self) -> Result<RedbUnit, Error> {
+        Ok(RedbUnit {
+            txn: self.db.begin_write()?,
+        })
+    }
+
+    fn put_in(&self, unit: &RedbUnit, account: AccountIndex, data: Into) -> Result<(), Error> {
+        let mut table = unit.txn.open_table(self.table)?;
+        table.insert(account, (self.transform.backward)(data))?;
+        Ok(())
+    }
+
+    fn revoke_in(&self, unit: &RedbUnit, account: AccountIndex) -> Result<Option<Into>, Error> {
+        let mut table = unit.txn.open_table(self.table)?;
+        let option = table.remove(account)?;
+        Ok(option.map(|s| (self.transform.forward)(s.value().clone())))
+    }
+
+    fn contains_in(&self, unit: &RedbUnit, account: AccountIndex) -> Result<bool, Error> {
+        let table = unit.txn.open_table(self.table)?;
+        let contains = table.get(account)?.is_some();
+        Ok(contains)
+    }
+}
+
+pub struct RedbUnit {
+    txn: WriteTransaction,
+}
+
+impl UnitOfWork for RedbUnit {
+    type Err = Error;
+
+    fn commit(self) -> Result<(), Error> {
+        self.txn.commit()?;
+        Ok(())
+    }
+}
+
 struct KeyIterator {
     vec: Vec<AccountIndex>,
     idx: usize,
@@ -201,3 +234,225 @@ pub struct Transformer<From, Into> {
     pub forward: fn(From) -> Into,
     pub backward: fn(Into) -> From,
 }
+
+/// Due times stored as milliseconds since the Unix epoch.
+pub struct RedbSchedule {
+    table: TableDefinition<'static, &'static str, u64>,
+    db: Arc<Database>,
+}
+
+impl RedbSchedule {
+    pub fn new(table: TableDefinition<'static, &'static str, u64>, db: Arc<Database>) -> Self {
+        Self { table, db }
+    }
+}
+
+impl ScheduleRepository for RedbSchedule {
+    type Err = Error;
+
+    fn due(&self, job: &str) -> Result<Option<SystemTime>, Error> {
+        let read_txn = self.db.begin_read()?;
+        let table = read_txn.open_table(self.table)?;
+        Ok(table
+            .get(job)?
+            .map(|millis| UNIX_EPOCH + Duration::from_millis(millis.value())))
+    }
+
+    fn set_due(&mut self, job: &str, due: SystemTime) -> Result<(), Error> {
+        let millis = due.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        let txn = self.db.begin_write()?;
+        txn.open_table(self.table)?.insert(job, millis)?;
+        txn.commit()?;
+        Ok(())
+    }
+
+    fn clear(&mut self, job: &str) -> Result<(), Error> {
+        let txn = self.db.begin_write()?;
+        txn.open_table(self.table)?.remove(job)?;
+        txn.commit()?;
+        Ok(())
+    }
+
+    fn jobs(&self) -> Result<Vec<String>, Error> {
+        let read_txn = self.db.begin_read()?;
+        let table = read_txn.open_table(self.table)?;
+        let jobs = table
+            .iter()?
+            .map(|e| e.map(|(job, _)| job.value().to_string()))
+            .collect::<Result<_, _>>()?;
+        Ok(jobs)
+    }
+}
+
+/// Dialogue state of each chat, stored as BSON like the rest of the database so it
+/// survives restarts.
+pub struct RedbDialogues {
+    table: TableDefinition<'static, i64, Vec<u8>>,
+    db: Arc<Database>,
+}
+
+impl RedbDialogues {
+    pub fn new(table: TableDefinition<'static, i64, Vec<u8>>, db: Arc<Database>) -> Self {
+        Self { table, db }
+    }
+}
+
+#[derive(Debug)]
+pub enum DialogueError {
+    Database(Error),
+    Encoding(bson::ser::Error),
+    Decoding(bson::de::Error),
+}
+
+impl Display for DialogueError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DialogueError::Database(e) => write!(f, "database: {}", e),
+            DialogueError::Encoding(e) => write!(f, "encoding: {}", e),
+            DialogueError::Decoding(e) => write!(f, "decoding: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DialogueError {}
+
+impl<E: Into<Error>> From<E> for DialogueError {
+    fn from(value: E) -> Self {
+        DialogueError::Database(value.into())
+    }
+}
+
+/// BSON wants a document at the top level, which a bare state isn't.
+#[derive(Serialize, Deserialize)]
+struct StoredDialogue<D> {
+    dialogue: D,
+}
+
+type DialogueFuture<T> = Pin<Box<dyn Future<Output = Result<T, DialogueError>> + Send>>;
+
+impl<D> Storage<D> for RedbDialogues
+where
+    D: Serialize + DeserializeOwned + Send + 'static,
+{
+    type Error = DialogueError;
+
+    fn remove_dialogue(self: Arc<Self>, chat_id: ChatId) -> DialogueFuture<()> {
+        Box::pin(async move {
+            let txn = self.db.begin_write()?;
+            txn.open_table(self.table)?.remove(chat_id.0)?;
+            txn.commit()?;
+            Ok(())
+        })
+    }
+
+    fn update_dialogue(self: Arc<Self>, chat_id: ChatId, dialogue: D) -> DialogueFuture<()> {
+        Box::pin(async move {
+            let encoded = bson::to_vec(&StoredDialogue { dialogue })
+                .map_err(DialogueError::Encoding)?;
+            let txn = self.db.begin_write()?;
+            txn.open_table(self.table)?.insert(chat_id.0, encoded)?;
+            txn.commit()?;
+            Ok(())
+        })
+    }
+
+    fn get_dialogue(self: Arc<Self>, chat_id: ChatId) -> DialogueFuture<Option<D>> {
+        Box::pin(async move {
+            let read_txn = self.db.begin_read()?;
+            let table = read_txn.open_table(self.table)?;
+            let Some(encoded) = table.get(chat_id.0)? else {
+                return Ok(None);
+            };
+            bson::from_slice::<StoredDialogue<D>>(&encoded.value())
+                .map(|stored| Some(stored.dialogue))
+                .map_err(DialogueError::Decoding)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::schema::tests::in_memory_db;
+
+    const FIRST: AccountIndexedTable<String> = TableDefinition::new("first");
+    const SECOND: AccountIndexedTable<u32> = TableDefinition::new("second");
+
+    #[test]
+    fn unit_commits_across_tables() {
+        let db = Arc::new(in_memory_db());
+        let first = RedbRepo::new(FIRST, db.clone());
+        let second = RedbRepo::new(SECOND, db);
+
+        let unit = first.begin().unwrap();
+        first.put_in(&unit, 1, "one".to_string()).unwrap();
+        second.put_in(&unit, 1, 1).unwrap();
+        unit.commit().unwrap();
+
+        assert_eq!(first.get(1).unwrap().as_deref(), Some("one"));
+        assert_eq!(second.get(1).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn dropped_unit_leaves_no_trace() {
+        let db = Arc::new(in_memory_db());
+        let mut first = RedbRepo::new(FIRST, db.clone());
+        let second = RedbRepo::new(SECOND, db);
+        first.put(1, "one".to_string()).unwrap();
+
+        {
+            let unit = first.begin().unwrap();
+            first.revoke_in(&unit, 1).unwrap();
+            second.put_in(&unit, 1, 1).unwrap();
+        }
+
+        assert_eq!(first.get(1).unwrap().as_deref(), Some("one"));
+        assert_eq!(second.entries().unwrap().count(), 0);
+    }
+
+    #[test]
+    fn schedule_round_trips() {
+        const JOBS: TableDefinition<&str, u64> = TableDefinition::new("jobs");
+        let mut schedule = RedbSchedule::new(JOBS, Arc::new(in_memory_db()));
+        let due = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        schedule.set_due("backup", due).unwrap();
+        schedule.set_due("poll:1", due).unwrap();
+        assert_eq!(schedule.due("backup").unwrap(), Some(due));
+        schedule.clear("backup").unwrap();
+        assert_eq!(schedule.due("backup").unwrap(), None);
+        assert_eq!(schedule.jobs().unwrap(), vec!["poll:1".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn dialogues_round_trip() {
+        const DIALOGUES: TableDefinition<i64, Vec<u8>> = TableDefinition::new("dialogues");
+        let db = Arc::new(in_memory_db());
+        let txn = db.begin_write().unwrap();
+        txn.open_table(DIALOGUES).unwrap();
+        txn.commit().unwrap();
+        let storage = Arc::new(RedbDialogues::new(DIALOGUES, db));
+        let chat = ChatId(-42);
+        let get = |storage: &Arc<RedbDialogues>| {
+            Storage::<String>::get_dialogue(storage.clone(), chat)
+        };
+        assert_eq!(get(&storage).await.unwrap(), None);
+        storage
+            .clone()
+            .update_dialogue(chat, "token".to_string())
+            .await
+            .unwrap();
+        assert_eq!(get(&storage).await.unwrap().as_deref(), Some("token"));
+        Storage::<String>::remove_dialogue(storage.clone(), chat)
+            .await
+            .unwrap();
+        assert_eq!(get(&storage).await.unwrap(), None);
+    }
+
+    #[test]
+    fn revoke_persists() {
+        let mut repo = RedbRepo::new(FIRST, Arc::new(in_memory_db()));
+        repo.put(1, "one".to_string()).unwrap();
+        assert_eq!(repo.revoke(1).unwrap().as_deref(), Some("one"));
+        assert_eq!(repo.get(1).unwrap(), None);
+    }
+}
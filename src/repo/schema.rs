@@ -0,0 +1,440 @@
+use crate::repo::crypto::Keyring;
+use crate::repo::model::AccountIndex;
+use crate::repo::settings::UserSettings;
+use redb::{
+    Database, Key, ReadTransaction, ReadableTable, TableDefinition, TableError, Value,
+    WriteTransaction,
+};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const METADATA_TABLE: TableDefinition<&str, u64> = TableDefinition::new("metadata");
+pub const TOKENS_TABLE: TableDefinition<AccountIndex, String> = TableDefinition::new("tokens");
+pub const PROGRESS_TABLE: TableDefinition<AccountIndex, Vec<u8>> = TableDefinition::new("progress");
+pub const INTERVAL_TABLE: TableDefinition<AccountIndex, u32> = TableDefinition::new("interval");
+pub const SETTINGS_TABLE: TableDefinition<AccountIndex, Vec<u8>> = TableDefinition::new("settings");
+/// Next time each account is due for a poll, in milliseconds since the Unix epoch.
+/// Superseded by [`JOBS_TABLE`] in version 5.
+pub const SCHEDULE_TABLE: TableDefinition<AccountIndex, u64> = TableDefinition::new("schedule");
+/// Next time each scheduled job is due, in milliseconds since the Unix epoch, keyed
+/// by the job's name.
+pub const JOBS_TABLE: TableDefinition<&str, u64> = TableDefinition::new("jobs");
+/// Notifications of each account, see [`Ledger`](crate::repo::ledger::Ledger).
+pub const LEDGER_TABLE: TableDefinition<AccountIndex, Vec<u8>> = TableDefinition::new("ledger");
+/// Where each chat stands in a conversation with the bot, JSON encoded, keyed by
+/// chat id.
+pub const DIALOGUES_TABLE: TableDefinition<i64, Vec<u8>> = TableDefinition::new("dialogues");
+
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+type MigrationStep = Box<dyn Fn(&WriteTransaction) -> Result<(), Error>>;
+
+/// A single upgrade step, bringing the database from `version - 1` to `version`.
+pub struct Migration {
+    pub version: u64,
+    pub description: &'static str,
+    pub apply: MigrationStep,
+}
+
+pub fn migrations(keyring: Arc<Keyring>) -> Vec<Migration> {
+    vec![
+        Migration {
+            version: 1,
+            description: "baseline tokens, progress and interval tables",
+            apply: Box::new(|txn| {
+                txn.open_table(TOKENS_TABLE)?;
+                txn.open_table(PROGRESS_TABLE)?;
+                txn.open_table(INTERVAL_TABLE)?;
+                Ok(())
+            }),
+        },
+        Migration {
+            version: 2,
+            description: "seal plaintext tokens",
+            apply: Box::new(move |txn| {
+                let mut table = txn.open_table(TOKENS_TABLE)?;
+                let plaintext: Vec<(AccountIndex, String)> = table
+                    .iter()?
+                    .map(|e| e.map(|(k, v)| (k.value(), v.value())))
+                    .filter(|e| e.as_ref().map_or(true, |(_, v)| !Keyring::is_sealed(v)))
+                    .collect::<Result<_, _>>()?;
+                for (account, token) in plaintext {
+                    table.insert(account, keyring.seal(account, &token))?;
+                }
+                Ok(())
+            }),
+        },
+        Migration {
+            version: 3,
+            description: "move polling intervals into per-user settings",
+            apply: Box::new(|txn| {
+                let intervals = txn.open_table(INTERVAL_TABLE)?;
+                let mut settings = txn.open_table(SETTINGS_TABLE)?;
+                for entry in intervals.iter()? {
+                    let (account, minutes) = entry?;
+                    let record = UserSettings::with_interval(Duration::from_secs(
+                        minutes.value() as u64 * 60,
+                    ));
+                    settings.insert(
+                        account.value(),
+                        bson::to_vec(&record).map_err(Error::Encoding)?,
+                    )?;
+                }
+                drop(intervals);
+                txn.delete_table(INTERVAL_TABLE)?;
+                Ok(())
+            }),
+        },
+        Migration {
+            version: 4,
+            description: "persisted polling schedule",
+            apply: Box::new(|txn| {
+                txn.open_table(SCHEDULE_TABLE)?;
+                Ok(())
+            }),
+        },
+        Migration {
+            version: 5,
+            description: "keep polls and maintenance jobs in one schedule",
+            apply: Box::new(|txn| {
+                let schedule = txn.open_table(SCHEDULE_TABLE)?;
+                let mut jobs = txn.open_table(JOBS_TABLE)?;
+                for entry in schedule.iter()? {
+                    let (account, due) = entry?;
+                    jobs.insert(format!("poll:{}", account.value()).as_str(), due.value())?;
+                }
+                drop(schedule);
+                txn.delete_table(SCHEDULE_TABLE)?;
+                Ok(())
+            }),
+        },
+        Migration {
+            version: 6,
+            description: "notification ledger",
+            apply: Box::new(|txn| {
+                txn.open_table(LEDGER_TABLE)?;
+                Ok(())
+            }),
+        },
+        Migration {
+            version: 7,
+            description: "dialogue state",
+            apply: Box::new(|txn| {
+                txn.open_table(DIALOGUES_TABLE)?;
+                Ok(())
+            }),
+        },
+    ]
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Database(Box<redb::Error>),
+    Downgrade { found: u64, supported: u64 },
+    Unordered(u64),
+    Encoding(bson::ser::Error),
+    IO(std::io::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Database(e) => write!(f, "database: {}", e),
+            Error::Downgrade { found, supported } => write!(
+                f,
+                "database schema version {} is newer than the supported version {}",
+                found, supported
+            ),
+            Error::Unordered(version) => {
+                write!(f, "migration to version {} is out of order", version)
+            }
+            Error::Encoding(e) => write!(f, "encoding: {}", e),
+            Error::IO(e) => write!(f, "io: {}", e),
+        }
+    }
+}
+
+impl<E: Into<redb::Error>> From<E> for Error {
+    fn from(value: E) -> Self {
+        Error::Database(Box::new(value.into()))
+    }
+}
+
+pub fn get_version(txn: &WriteTransaction) -> Result<u64, Error> {
+    let table = txn.open_table(METADATA_TABLE)?;
+    let version = table.get(SCHEMA_VERSION_KEY)?.map_or(0, |v| v.value());
+    Ok(version)
+}
+
+/// Runs every migration newer than the stored schema version, in order and inside
+/// one write transaction, so a failing step leaves the database untouched.
+///
+/// Returns the schema version the database ends up with.
+pub fn migrate(db: &Database, migrations: &[Migration]) -> Result<u64, Error> {
+    let supported = migrations.last().map_or(0, |m| m.version);
+    let txn = db.begin_write()?;
+    let mut version = get_version(&txn)?;
+    if version > supported {
+        return Err(Error::Downgrade {
+            found: version,
+            supported,
+        });
+    }
+
+    let pending = migrations.iter().filter(|m| m.version > version);
+    for migration in pending.collect::<Vec<_>>() {
+        if migration.version != version + 1 {
+            return Err(Error::Unordered(migration.version));
+        }
+        println!(
+            "Migrating database to version {}: {}",
+            migration.version, migration.description
+        );
+        (migration.apply)(&txn)?;
+        version = migration.version;
+    }
+
+    txn.open_table(METADATA_TABLE)?
+        .insert(SCHEMA_VERSION_KEY, version)?;
+    txn.commit()?;
+    Ok(version)
+}
+
+/// Copies every table of the current schema into a new database at `path`,
+/// replacing the file only once the copy is complete. Tokens stay sealed.
+///
+/// Tables added to the schema need to be listed here too.
+pub fn backup(db: &Database, path: &Path) -> Result<(), Error> {
+    let partial = path.with_extension("partial");
+    if partial.exists() {
+        fs::remove_file(&partial).map_err(Error::IO)?;
+    }
+    {
+        let target = Database::create(&partial)?;
+        let from = db.begin_read()?;
+        let to = target.begin_write()?;
+        copy_table(&from, &to, METADATA_TABLE)?;
+        copy_table(&from, &to, TOKENS_TABLE)?;
+        copy_table(&from, &to, PROGRESS_TABLE)?;
+        copy_table(&from, &to, SETTINGS_TABLE)?;
+        copy_table(&from, &to, JOBS_TABLE)?;
+        copy_table(&from, &to, LEDGER_TABLE)?;
+        copy_table(&from, &to, DIALOGUES_TABLE)?;
+        to.commit()?;
+    }
+    fs::rename(&partial, path).map_err(Error::IO)
+}
+
+fn copy_table<K: Key + 'static, V: Value + 'static>(
+    from: &ReadTransaction,
+    to: &WriteTransaction,
+    definition: TableDefinition<K, V>,
+) -> Result<(), Error> {
+    let source = match from.open_table(definition) {
+        Ok(table) => table,
+        Err(TableError::TableDoesNotExist(_)) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut target = to.open_table(definition)?;
+    for entry in source.iter()? {
+        let (key, value) = entry?;
+        target.insert(key.value(), value.value())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::repo::redb::{RedbRepo, Transformer};
+    use redb::backends::InMemoryBackend;
+    use redb::ReadableTableMetadata;
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+
+    const KEY: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+
+    fn keyring() -> Arc<Keyring> {
+        Arc::new(Keyring::new(KEY, []).unwrap())
+    }
+
+    pub fn in_memory_db() -> Database {
+        Database::builder()
+            .create_with_backend(InMemoryBackend::new())
+            .expect("Failed to create in-memory database")
+    }
+
+    /// A repository of records kept as BSON, like settings, progress and the
+    /// ledger are.
+    pub fn bson_repo<T: Serialize + DeserializeOwned>(
+        table: TableDefinition<'static, AccountIndex, Vec<u8>>,
+        db: Arc<Database>,
+    ) -> RedbRepo<Vec<u8>, T> {
+        RedbRepo::new_proxy(
+            table,
+            db,
+            Transformer {
+                forward: |e| bson::from_slice::<T>(e.as_slice()).unwrap(),
+                backward: |e| bson::to_vec(&e).unwrap(),
+            },
+        )
+    }
+
+    pub fn settings_repo(db: Arc<Database>) -> RedbRepo<Vec<u8>, UserSettings> {
+        bson_repo(SETTINGS_TABLE, db)
+    }
+
+    /// A database as written by releases predating the metadata table.
+    pub fn legacy_fixture() -> Database {
+        let db = in_memory_db();
+        let txn = db.begin_write().unwrap();
+        {
+            let mut tokens = txn.open_table(TOKENS_TABLE).unwrap();
+            tokens.insert(1, "token-one".to_string()).unwrap();
+            tokens.insert(2, "token-two".to_string()).unwrap();
+            let mut intervals = txn.open_table(INTERVAL_TABLE).unwrap();
+            intervals.insert(1, 30).unwrap();
+            txn.open_table(PROGRESS_TABLE).unwrap();
+        }
+        txn.commit().unwrap();
+        db
+    }
+
+    fn stored_version(db: &Database) -> u64 {
+        let txn = db.begin_write().unwrap();
+        get_version(&txn).unwrap()
+    }
+
+    #[test]
+    fn upgrades_legacy_database() {
+        let db = legacy_fixture();
+        let latest = migrations(keyring()).last().unwrap().version;
+        assert_eq!(migrate(&db, &migrations(keyring())).unwrap(), latest);
+        assert_eq!(stored_version(&db), latest);
+
+        let txn = db.begin_read().unwrap();
+        let tokens = txn.open_table(TOKENS_TABLE).unwrap();
+        let sealed = tokens.get(2).unwrap().unwrap().value();
+        assert!(Keyring::is_sealed(&sealed));
+        assert_eq!(keyring().open(2, &sealed).unwrap(), "token-two");
+        let settings = txn.open_table(SETTINGS_TABLE).unwrap();
+        let record: UserSettings =
+            bson::from_slice(&settings.get(1).unwrap().unwrap().value()).unwrap();
+        assert_eq!(record.interval, Some(Duration::from_secs(30 * 60)));
+        assert!(settings.get(2).unwrap().is_none());
+        assert!(txn.open_table(INTERVAL_TABLE).is_err());
+    }
+
+    #[test]
+    fn moves_schedule_into_jobs() {
+        let db = legacy_fixture();
+        let mut up_to_schedule = migrations(keyring());
+        up_to_schedule.truncate(4);
+        migrate(&db, &up_to_schedule).unwrap();
+        let txn = db.begin_write().unwrap();
+        txn.open_table(SCHEDULE_TABLE)
+            .unwrap()
+            .insert(1, 1_700_000_000_000)
+            .unwrap();
+        txn.commit().unwrap();
+
+        migrate(&db, &migrations(keyring())).unwrap();
+        let txn = db.begin_read().unwrap();
+        let jobs = txn.open_table(JOBS_TABLE).unwrap();
+        assert_eq!(
+            jobs.get("poll:1").unwrap().map(|due| due.value()),
+            Some(1_700_000_000_000)
+        );
+        assert!(txn.open_table(SCHEDULE_TABLE).is_err());
+    }
+
+    #[test]
+    fn backs_up_every_table() {
+        let db = legacy_fixture();
+        migrate(&db, &migrations(keyring())).unwrap();
+        let path = std::env::temp_dir().join(format!("qazer-backup-{}.redb", std::process::id()));
+        backup(&db, &path).unwrap();
+
+        let copy = Database::open(&path).unwrap();
+        let txn = copy.begin_read().unwrap();
+        assert_eq!(txn.open_table(TOKENS_TABLE).unwrap().len().unwrap(), 2);
+        assert!(txn
+            .open_table(SETTINGS_TABLE)
+            .unwrap()
+            .get(1)
+            .unwrap()
+            .is_some());
+        drop(txn);
+        assert_eq!(
+            stored_version(&copy),
+            migrations(keyring()).last().unwrap().version
+        );
+        drop(copy);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn initializes_fresh_database() {
+        let db = in_memory_db();
+        migrate(&db, &migrations(keyring())).unwrap();
+        let txn = db.begin_read().unwrap();
+        assert!(txn.open_table(TOKENS_TABLE).unwrap().is_empty().unwrap());
+    }
+
+    #[test]
+    fn migration_is_idempotent() {
+        let db = legacy_fixture();
+        let first = migrate(&db, &migrations(keyring())).unwrap();
+        assert_eq!(migrate(&db, &migrations(keyring())).unwrap(), first);
+    }
+
+    #[test]
+    fn refuses_downgrade() {
+        let db = legacy_fixture();
+        migrate(&db, &migrations(keyring())).unwrap();
+        let mut older = migrations(keyring());
+        older.pop();
+        assert!(matches!(migrate(&db, &older), Err(Error::Downgrade { .. })));
+    }
+
+    #[test]
+    fn failing_step_rolls_back() {
+        let db = legacy_fixture();
+        let migrations = [
+            Migration {
+                version: 1,
+                description: "wipe tokens",
+                apply: Box::new(|txn| {
+                    txn.delete_table(TOKENS_TABLE)?;
+                    Ok(())
+                }),
+            },
+            Migration {
+                version: 2,
+                description: "fail",
+                apply: Box::new(|_| Err(redb::Error::Corrupted("fixture".into()).into())),
+            },
+        ];
+        assert!(migrate(&db, &migrations).is_err());
+        assert_eq!(stored_version(&db), 0);
+        let txn = db.begin_read().unwrap();
+        assert_eq!(txn.open_table(TOKENS_TABLE).unwrap().len().unwrap(), 2);
+    }
+
+    #[test]
+    fn rejects_gaps() {
+        let db = in_memory_db();
+        let migrations = [Migration {
+            version: 2,
+            description: "skips version 1",
+            apply: Box::new(|_| Ok(())),
+        }];
+        assert!(matches!(
+            migrate(&db, &migrations),
+            Err(Error::Unordered(2))
+        ));
+    }
+}
@@ -0,0 +1,235 @@
+use crate::tencent::progress::Step;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Everything a user can configure about their account, stored as one record so
+/// adding a preference doesn't take another table.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct UserSettings {
+    /// Time between two polls, or `None` if polling is turned off.
+    pub interval: Option<Duration>,
+    /// Bounds the polling interval adapts within, or `None` to stick to `interval`.
+    pub adaptive: Option<AdaptivePolling>,
+    /// Preferred language tag. `None` follows the Telegram client.
+    pub language: Option<String>,
+    /// Language tag of the user's Telegram client when last seen, for messages
+    /// sent on the bot's own initiative.
+    pub client_language: Option<String>,
+    pub quiet_hours: Option<QuietHours>,
+    pub filter: NotificationFilter,
+    /// Where notifications are forwarded to besides Telegram.
+    pub webhook: Option<WebhookTarget>,
+    /// Address notifications are also emailed to.
+    pub email: Option<EmailTarget>,
+    /// When confirmation codes were mailed lately. Kept apart from `email`, so
+    /// turning email off doesn't reset the daily cap.
+    pub code_sends: Vec<SystemTime>,
+}
+
+/// A daily window, in minutes since local midnight, during which the user doesn't
+/// want to be disturbed. The window may wrap around midnight.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct QuietHours {
+    pub start: u16,
+    pub end: u16,
+    /// Offset of the user's time zone from UTC, in minutes.
+    pub utc_offset: i16,
+    /// Whether urgent notifications come through anyway.
+    #[serde(default)]
+    pub urgent: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct WebhookTarget {
+    pub url: String,
+    /// Key the payloads are signed with, so the receiving end can tell they're
+    /// genuine.
+    pub secret: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EmailTarget {
+    pub address: String,
+    /// Code mailed to the address, which has to be given back through the bot
+    /// before anything else is sent there. `None` once confirmed.
+    pub code: Option<String>,
+    /// Wrong codes given so far.
+    #[serde(default)]
+    pub attempts: u32,
+}
+
+impl EmailTarget {
+    pub fn is_confirmed(&self) -> bool {
+        self.code.is_none()
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+pub struct AdaptivePolling {
+    pub min: Duration,
+    pub max: Duration,
+}
+
+/// Which changes of the progress the user is notified about.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum NotificationFilter {
+    #[default]
+    All,
+    /// Only changes that move the application to another step.
+    StepChanges,
+    /// Only changes while the application is at the step or past it.
+    From(Step),
+    /// Only assessments and written tests to take.
+    Assessments,
+}
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+impl QuietHours {
+    /// Seconds since local midnight at `at`.
+    fn local_seconds(&self, at: SystemTime) -> i64 {
+        let unix = match at.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64),
+        };
+        (unix + self.utc_offset as i64 * 60).rem_euclid(SECONDS_PER_DAY)
+    }
+
+    /// Whether `at` falls into the window. A window starting and ending at the
+    /// same minute is empty.
+    pub fn contains(&self, at: SystemTime) -> bool {
+        let now = self.local_seconds(at);
+        let (start, end) = (self.start as i64 * 60, self.end as i64 * 60);
+        if start <= end {
+            start <= now && now < end
+        } else {
+            now >= start || now < end
+        }
+    }
+
+    /// Time from `at` until the window next ends.
+    pub fn until_end(&self, at: SystemTime) -> Duration {
+        let remaining = (self.end as i64 * 60 - self.local_seconds(at)).rem_euclid(SECONDS_PER_DAY);
+        Duration::from_secs(remaining as u64)
+    }
+}
+
+/// Parses `<start> <end> <UTC offset> [urgent]`, e.g. `23:00 07:30 +08:00 urgent`.
+impl FromStr for QuietHours {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let args: Vec<_> = s.split_whitespace().collect();
+        let (start, end, offset, urgent) = match args[..] {
+            [start, end, offset] => (start, end, offset, false),
+            [start, end, offset, "urgent"] => (start, end, offset, true),
+            _ => return Err("expected a start, an end, a UTC offset and optionally \"urgent\""),
+        };
+        let offset = parse_utc_offset(offset).ok_or("UTC offset should look like +08:00")?;
+        Ok(QuietHours {
+            start: parse_time_of_day(start).ok_or("start should look like 23:00")?,
+            end: parse_time_of_day(end).ok_or("end should look like 07:00")?,
+            utc_offset: offset,
+            urgent,
+        })
+    }
+}
+
+/// Minutes since midnight of `HH:MM`.
+fn parse_time_of_day(s: &str) -> Option<u16> {
+    let (hours, minutes) = s.split_once(':')?;
+    let (hours, minutes): (u16, u16) = (hours.parse().ok()?, minutes.parse().ok()?);
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+/// Minutes east of UTC of `±HH[:MM]`.
+fn parse_utc_offset(s: &str) -> Option<i16> {
+    let (sign, rest) = match s.split_at_checked(1)? {
+        ("+", rest) => (1, rest),
+        ("-", rest) => (-1, rest),
+        _ => return None,
+    };
+    let (hours, minutes) = rest.split_once(':').unwrap_or((rest, "0"));
+    let (hours, minutes): (i16, i16) = (hours.parse().ok()?, minutes.parse().ok()?);
+    (hours <= 14 && minutes < 60).then_some(sign * (hours * 60 + minutes))
+}
+
+impl UserSettings {
+    pub fn with_interval(interval: Duration) -> Self {
+        Self {
+            interval: Some(interval),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_fields_fall_back_to_defaults() {
+        // format was dropped, and records still carrying it read fine
+        let partial = bson::to_vec(&bson::doc! { "language": "zh-CN", "format": "plain" }).unwrap();
+        let settings: UserSettings = bson::from_slice(&partial).unwrap();
+        assert_eq!(settings.language.as_deref(), Some("zh-CN"));
+        assert_eq!(settings.interval, None);
+        assert_eq!(settings.filter, NotificationFilter::All);
+    }
+
+    #[test]
+    fn round_trips_through_bson() {
+        let settings = UserSettings {
+            quiet_hours: Some(QuietHours {
+                start: 23 * 60,
+                end: 7 * 60,
+                utc_offset: 8 * 60,
+                urgent: false,
+            }),
+            code_sends: vec![UNIX_EPOCH + Duration::from_secs(1_700_000_000)],
+            ..UserSettings::with_interval(Duration::from_secs(3600))
+        };
+        let encoded = bson::to_vec(&settings).unwrap();
+        assert_eq!(
+            bson::from_slice::<UserSettings>(&encoded).unwrap(),
+            settings
+        );
+    }
+
+    #[test]
+    fn quiet_hours_wrap_around_midnight() {
+        let quiet: QuietHours = "23:00 07:30 +08:00".parse().unwrap();
+        assert_eq!(quiet.start, 23 * 60);
+        assert_eq!(quiet.utc_offset, 8 * 60);
+        // 2024-01-01T00:00:00Z, which is 08:00 at UTC+8
+        let midnight_utc = UNIX_EPOCH + Duration::from_secs(1_704_067_200);
+        let hour = Duration::from_secs(3600);
+        assert!(!quiet.contains(midnight_utc));
+        assert!(quiet.contains(midnight_utc - hour * 9));
+        assert!(quiet.contains(midnight_utc - hour));
+        assert_eq!(quiet.until_end(midnight_utc - hour), hour / 2);
+        assert_eq!(quiet.until_end(midnight_utc - hour * 9), hour * 17 / 2);
+    }
+
+    #[test]
+    fn parses_quiet_hours() {
+        let quiet: QuietHours = "22:15 06:00 -05:30 urgent".parse().unwrap();
+        assert_eq!(
+            quiet,
+            QuietHours {
+                start: 22 * 60 + 15,
+                end: 6 * 60,
+                utc_offset: -(5 * 60 + 30),
+                urgent: true,
+            }
+        );
+        assert!("25:00 06:00 +00:00".parse::<QuietHours>().is_err());
+        assert!("22:00 06:00 8".parse::<QuietHours>().is_err());
+        assert!("22:00 06:00".parse::<QuietHours>().is_err());
+    }
+}
@@ -1,18 +1,21 @@
+use crate::clock::{SharedClock, SystemClock};
 use crate::tencent::error::Error;
 use crate::tencent::model::{ApplicationProgress, GetApplyProcessResponse};
 use reqwest::cookie::Jar;
 use reqwest::header::ACCEPT;
 use reqwest::Url;
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::UNIX_EPOCH;
 
-const JOIN_QQ: &str = "https://join.qq.com";
+pub const JOIN_QQ: &str = "https://join.qq.com";
 
 pub type ClientResult<T> = Result<T, Error>;
 
 pub struct Client {
     client: reqwest::Client,
     jar: Arc<Jar>,
+    clock: SharedClock,
+    base_url: String,
 }
 
 impl Client {
@@ -26,9 +29,25 @@ impl Client {
         Self {
             jar: jar.clone(),
             client,
+            clock: SystemClock::shared(),
+            base_url: JOIN_QQ.into(),
         }
     }
 
+    /// Replaces the clock the request timestamps are taken from.
+    #[cfg(test)]
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Replaces the server the progress is fetched from.
+    #[cfg(test)]
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
     pub fn with_token(token: &String) -> Self {
         let instance = Self::new();
         instance.update_token(token);
@@ -41,27 +60,33 @@ impl Client {
             .add_cookie_str(format!("UserInfo={}", value).as_str(), &url);
     }
 
-    pub async fn get_application_progress(&self) -> ClientResult<ApplicationProgress> {
-        let now = SystemTime::now()
+    fn progress_url(&self) -> String {
+        let now = self
+            .clock
+            .system_now()
             .duration_since(UNIX_EPOCH)
             .expect("Time went backwards.");
-        let url = format!(
+        format!(
             "{}/api/v1/apply/getApplyProcess?timestamp={}",
-            JOIN_QQ,
+            self.base_url,
             now.as_millis()
-        );
+        )
+    }
+
+    pub async fn get_application_progress(&self) -> ClientResult<ApplicationProgress> {
+        let url = self.progress_url();
         let res = self
             .client
             .get(url)
             .header(ACCEPT, "application/json")
             .send()
             .await
-            .map_err(|e| Error::Http(e))?;
+            .map_err(Error::Http)?;
         if res.status().is_success() {
             Ok(res
                 .json::<GetApplyProcessResponse>()
                 .await
-                .map_err(|e| Error::Parse(e))?)
+                .map_err(Error::Parse)?)
                 .map(|r| r.data)
         } else if res.status().is_client_error() {
             Err(Error::TokenExpired)
@@ -73,9 +98,20 @@ impl Client {
 
 #[cfg(test)]
 mod tests {
+    use crate::clock::ManualClock;
     use crate::tencent::Client;
     use std::env;
     use std::env::VarError;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn stamps_requests_with_clock() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123));
+        let client = Client::new().with_clock(clock.clone());
+        assert!(client.progress_url().ends_with("?timestamp=1700000000123"));
+        clock.advance(Duration::from_secs(1));
+        assert!(client.progress_url().ends_with("?timestamp=1700000001123"));
+    }
 
     #[tokio::test]
     async fn fetches_ap() {
@@ -1,12 +1,10 @@
 use std::fmt::{Display, Formatter};
-use std::io;
 
 #[derive(Debug)]
 pub enum Error {
     TokenExpired,
     Http(reqwest::Error),
     Parse(reqwest::Error),
-    IO(io::Error),
 }
 
 impl Display for Error {
@@ -15,7 +13,6 @@ impl Display for Error {
             Error::TokenExpired => write!(f, "token expired"),
             Error::Http(e) => write!(f, "http: {}", e),
             Error::Parse(e) => write!(f, "parser: {}", e),
-            Error::IO(e) => write!(f, "io: {}", e),
         }
     }
 }
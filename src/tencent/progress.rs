@@ -1,7 +1,10 @@
 use crate::tencent::model::ApplicationProgress;
+use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Formatter};
 
-#[derive(Debug)]
+/// Steps of an application, in the order they're taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub enum Step {
     CvDeliverance,
     Examination,
@@ -17,6 +20,23 @@ pub enum Step {
     Completed,
 }
 
+impl Step {
+    pub const ALL: [Step; 12] = [
+        Step::CvDeliverance,
+        Step::Examination,
+        Step::WrittenTest,
+        Step::GroupInterview,
+        Step::PreliminaryInterview,
+        Step::SecondaryInterview,
+        Step::HrInterview,
+        Step::EmployerAssessment,
+        Step::EmployeeConfirmation,
+        Step::OfferConfirmation,
+        Step::SignUp,
+        Step::Completed,
+    ];
+}
+
 impl ApplicationProgress {
     pub fn get_current_step(&self) -> Result<Option<Step>, Error> {
         if self.resume_status.status < 3 {
@@ -36,11 +56,11 @@ impl ApplicationProgress {
         }
 
         let mut r1_list = self.campus_recruit_one.item_list.clone();
-        r1_list.sort_by(|a, b| b.step_id.cmp(&a.step_id));
+        r1_list.sort_by_key(|item| std::cmp::Reverse(item.step_id));
 
         let r1_current = r1_list.iter().find(|item| item.status == 2);
         if let Some(item) = r1_current {
-            return recruit_one_step(item.step_id).map(|s| Some(s));
+            return recruit_one_step(item.step_id).map(Some);
         }
 
         if let (Some(first_step), Some(last_step)) = (r1_list.last(), r1_list.first()) {
@@ -50,11 +70,11 @@ impl ApplicationProgress {
         }
 
         let mut r2_list = self.campus_recruit_two.item_list.clone();
-        r2_list.sort_by(|a, b| b.step_id.cmp(&a.step_id));
+        r2_list.sort_by_key(|item| std::cmp::Reverse(item.step_id));
 
         let r2_current = r2_list.iter().find(|item| item.status == 2);
         if let Some(item) = r2_current {
-            return recruit_two_step(item.step_id).map(|s| Some(s));
+            return recruit_two_step(item.step_id).map(Some);
         }
 
         Ok(Some(Step::Completed)) // TODO: I haven't landed here yet
@@ -1,9 +1,85 @@
-use std::cmp::Ordering;
-use std::collections::LinkedList;
-use std::time::Duration;
-use tokio::sync::broadcast;
-use tokio::sync::broadcast::Sender;
-use tokio::{select, time};
+pub mod recurrence;
+
+use crate::clock::{SharedClock, SystemClock};
+use crate::watch::recurrence::Recurrence;
+use rand::Rng;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, SystemTime};
+use tokio::time::Instant;
+
+/// Upper bound on the gap between two overdue entries when restoring a schedule.
+const MAX_OVERDUE_STAGGER: Duration = Duration::from_secs(5);
+
+/// A change to the schedule requested from outside the monitoring loop.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum ScheduleChange<ID> {
+    Reschedule(ID, Duration),
+    Remove(ID),
+    /// Runs the job right away, after which it carries on from then.
+    RunNow(ID),
+    /// Picks the job back up if it was paused, running it right away.
+    Resume(ID),
+}
+
+/// Shifts periodic entries off their common grid, so ids sharing a period don't
+/// all come due at once. Both parts are fractions of the period.
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
+pub struct Jitter {
+    /// Upper bound of a fixed delay derived from the id, which keeps the same id in
+    /// the same phase across cycles and restarts.
+    pub phase: f64,
+    /// Bound of a random deviation, in either direction, drawn for every cycle.
+    pub spread: f64,
+}
+
+impl Jitter {
+    fn apply<ID: Hash>(&self, id: &ID, nominal: Instant, period: Duration) -> Instant {
+        let period = period.as_secs_f64();
+        let mut offset = self.phase * period * unit_fraction(id);
+        if self.spread > 0.0 {
+            offset += rand::thread_rng().gen_range(-self.spread..=self.spread) * period;
+        }
+        if offset >= 0.0 {
+            nominal + Duration::from_secs_f64(offset)
+        } else {
+            nominal
+                .checked_sub(Duration::from_secs_f64(-offset))
+                .unwrap_or(nominal)
+        }
+    }
+}
+
+/// Maps the id onto `[0, 1)`, the same way every time.
+fn unit_fraction<ID: Hash>(id: &ID) -> f64 {
+    let mut hasher = Fnv1a::default();
+    id.hash(&mut hasher);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// 64-bit FNV-1a. Unlike `DefaultHasher`, its output is fixed across Rust releases,
+/// so an account keeps its phase after the binary is rebuilt.
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
 
 #[derive(Eq, PartialEq, Copy, Clone)]
 struct WatchNode<ID>
@@ -11,108 +87,196 @@ where
     ID: Eq + Copy,
 {
     id: ID,
-    timer: Duration,
+    deadline: Instant,
+    /// Deadline before jitter was applied.
+    nominal: Instant,
+    seq: u64,
 }
 
+/// Schedules ids on absolute deadlines. Replaced and removed entries stay in the
+/// heap until they surface, and are told apart from live ones by their sequence
+/// number.
 pub struct Watcher<ID>
 where
-    ID: Eq + Copy,
+    ID: Eq + Copy + Hash,
 {
-    pq: LinkedList<WatchNode<ID>>,
-    change_chan: Sender<()>,
-    pending: usize,
+    heap: BinaryHeap<Reverse<WatchNode<ID>>>,
+    live: HashMap<ID, u64>,
+    seq: u64,
+    jitter: Jitter,
+    clock: SharedClock,
 }
 
 impl<ID> Watcher<ID>
 where
-    ID: Eq + Copy,
+    ID: Eq + Copy + Hash,
 {
     pub fn new() -> Self {
-        let (tx, _) = broadcast::channel(1);
+        Self::with_jitter(Jitter::default())
+    }
+
+    pub fn with_jitter(jitter: Jitter) -> Self {
+        Self::with_clock(SystemClock::shared(), jitter)
+    }
+
+    pub fn with_clock(clock: SharedClock, jitter: Jitter) -> Self {
         Self {
-            pq: LinkedList::new(),
-            change_chan: tx,
-            pending: 0,
+            heap: BinaryHeap::new(),
+            live: HashMap::new(),
+            seq: 0,
+            jitter,
+            clock,
         }
     }
 
-    pub fn push(&mut self, id: ID, timer: Duration) {
-        self._push(WatchNode { id, timer })
+    /// Adds entries from wall-clock due times persisted by a previous run. Entries
+    /// that fell due in the meantime, or never got a due time, are polled promptly
+    /// but spread over at most `window`, most overdue first.
+    pub fn restore(
+        &mut self,
+        entries: impl IntoIterator<Item = (ID, Option<SystemTime>)>,
+        window: Duration,
+    ) {
+        let now = self.clock.system_now();
+        let start = self.clock.now();
+        let mut overdue = Vec::new();
+        for (id, due) in entries {
+            match due.map(|due| due.duration_since(now)) {
+                Some(Ok(remaining)) if !remaining.is_zero() => {
+                    self.push_at(id, start + remaining);
+                }
+                Some(Ok(_)) => overdue.push((id, Duration::ZERO)),
+                Some(Err(e)) => overdue.push((id, e.duration())),
+                None => overdue.push((id, Duration::MAX)),
+            }
+        }
+
+        overdue.sort_by_key(|&(_, lateness)| std::cmp::Reverse(lateness));
+        let stagger = (window / overdue.len().max(1) as u32).min(MAX_OVERDUE_STAGGER);
+        for (idx, (id, _)) in overdue.into_iter().enumerate() {
+            self.push_at(id, start + stagger * idx as u32);
+        }
     }
 
-    pub fn peek(&self) -> Option<ID> {
-        self.pq.front().map(|e| e.id)
+    /// Schedules `id` after `timer`, replacing its pending entry if there is one.
+    pub fn push(&mut self, id: ID, timer: Duration) -> Instant {
+        let deadline = self.clock.now() + timer;
+        self.push_at(id, deadline);
+        deadline
     }
 
-    pub fn pop(&mut self) -> Option<ID> {
-        if let Some(peek) = self.pq.pop_front() {
-            let _ = self.change_chan.send(());
-            Some(peek.id)
-        } else {
-            None
-        }
+    /// Schedules `id` at `deadline`, replacing its pending entry if there is one.
+    pub fn push_at(&mut self, id: ID, deadline: Instant) {
+        self.insert(id, deadline, deadline);
     }
 
-    pub async fn next(&mut self) -> Option<ID> {
-        self.pending += 1;
-        let mut rx = self.change_chan.subscribe();
+    /// Schedules the cycle following the one due at `previous`, with jitter
+    /// applied, and returns when it will actually come due. Counting from the
+    /// previous nominal deadline rather than from now keeps the time spent handling
+    /// it, as well as the jitter, from adding up. Cycles missed altogether are
+    /// skipped instead of replayed.
+    pub fn push_periodic(&mut self, id: ID, previous: Instant, period: Duration) -> Instant {
+        let now = self.clock.now();
+        let nominal = (previous + period).max(now);
+        let deadline = self.jitter.apply(&id, nominal, period).max(now);
+        self.insert(id, nominal, deadline);
+        deadline
+    }
 
-        loop {
-            let peek = self.pq.front()?.clone();
-            select! {
-                _ = time::sleep(peek.timer) => {
-                    break
-                },
-                _ = rx.recv() => {}
+    /// Schedules the run following the one due at `previous` according to
+    /// `recurrence`, returning when it comes due, or `None` if it never does
+    /// again. Wall-clock rules are not jittered, as they name a time on purpose.
+    pub fn push_recurring(
+        &mut self,
+        id: ID,
+        previous: Instant,
+        recurrence: &Recurrence,
+    ) -> Option<Instant> {
+        match recurrence {
+            Recurrence::Every(period) => Some(self.push_periodic(id, previous, *period)),
+            Recurrence::Cron(cron) => {
+                let now = self.clock.system_now();
+                let next = cron.next_after(now)?;
+                let deadline = self.clock.now() + next.duration_since(now).unwrap_or_default();
+                self.push_at(id, deadline);
+                Some(deadline)
             }
         }
+    }
+
+    fn insert(&mut self, id: ID, nominal: Instant, deadline: Instant) {
+        self.seq += 1;
+        self.live.insert(id, self.seq);
+        self.heap.push(Reverse(WatchNode {
+            id,
+            deadline,
+            nominal,
+            seq: self.seq,
+        }));
+        self.compact();
+    }
+
+    /// Cancels the pending entry of `id`, returning whether there was one.
+    pub fn remove(&mut self, id: ID) -> bool {
+        let existed = self.live.remove(&id).is_some();
+        self.compact();
+        existed
+    }
+
+    pub fn contains(&self, id: ID) -> bool {
+        self.live.contains_key(&id)
+    }
 
-        let peek = self.pq.pop_front()?.clone();
-        for node in &mut self.pq {
-            node.timer = if node.timer < peek.timer {
-                Duration::default()
-            } else {
-                node.timer - peek.timer
+    /// Takes the earliest entry regardless of whether it is due yet, returning it
+    /// along with its nominal deadline.
+    pub fn pop(&mut self) -> Option<(ID, Instant)> {
+        self.skip_stale();
+        let Reverse(node) = self.heap.pop()?;
+        self.live.remove(&node.id);
+        Some((node.id, node.nominal))
+    }
+
+    /// Waits for the earliest entry to become due and takes it, returning the id
+    /// along with the nominal deadline it was due at, which is what the following
+    /// cycle should be counted from. Returns `None` right away if nothing is
+    /// scheduled.
+    ///
+    /// Dropping the future before it completes leaves the schedule untouched.
+    pub async fn next(&mut self) -> Option<(ID, Instant)> {
+        self.skip_stale();
+        let deadline = self.heap.peek()?.0.deadline;
+        self.clock.sleep_until(deadline).await;
+        self.pop()
+    }
+
+    fn skip_stale(&mut self) {
+        while let Some(Reverse(node)) = self.heap.peek() {
+            if self.live.get(&node.id) == Some(&node.seq) {
+                break;
             }
+            self.heap.pop();
         }
-        self.pending -= 1;
-        Some(peek.id)
     }
 
-    fn _push(&mut self, node: WatchNode<ID>) {
-        for (idx, &curr) in self.pq.iter().enumerate() {
-            if node < curr {
-                let mut rear = self.pq.split_off(idx);
-                rear.push_front(node);
-                self.pq.append(&mut rear);
-                if idx == 0 && self.pending > 0 {
-                    let _ = self.change_chan.send(());
-                }
-                return;
-            }
+    fn compact(&mut self) {
+        if self.heap.len() > 2 * self.live.len() + 16 {
+            let live = &self.live;
+            self.heap
+                .retain(|Reverse(node)| live.get(&node.id) == Some(&node.seq));
         }
-        self.pq.push_front(node);
-        let _ = self.change_chan.send(());
     }
 }
 
 impl<ID> FromIterator<(ID, Duration)> for Watcher<ID>
 where
-    ID: Eq + Copy
+    ID: Eq + Copy + Hash,
 {
     fn from_iter<T: IntoIterator<Item = (ID, Duration)>>(iter: T) -> Self {
-        let mut pq = Vec::from_iter(
-            iter.into_iter()
-                .map(|(id, dur)| WatchNode { id, timer: dur }),
-        );
-        pq.sort();
-        
-        let (tx, _) = broadcast::channel(1);
-        Self {
-            pq: LinkedList::from_iter(pq),
-            change_chan: tx,
-            pending: 0
+        let mut watcher = Self::new();
+        for (id, timer) in iter {
+            watcher.push(id, timer);
         }
+        watcher
     }
 }
 
@@ -130,6 +294,315 @@ where
     ID: Eq + Copy,
 {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.timer.cmp(&other.timer)
+        self.deadline
+            .cmp(&other.deadline)
+            .then(self.seq.cmp(&other.seq))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, ManualClock};
+    use std::future::{poll_fn, Future};
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::Poll;
+    use tokio::time;
+
+    fn drain(watcher: &mut Watcher<u32>) -> Vec<u32> {
+        std::iter::from_fn(|| watcher.pop().map(|(id, _)| id)).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keeps_one_entry_per_id() {
+        let mut watcher = Watcher::new();
+        watcher.push(1, Duration::from_secs(10));
+        watcher.push(2, Duration::from_secs(20));
+        assert!(watcher.contains(1));
+        watcher.push(1, Duration::from_secs(30));
+        assert_eq!(drain(&mut watcher), vec![2, 1]);
+        assert!(!watcher.contains(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn removes_entries() {
+        let mut watcher = Watcher::new();
+        watcher.push(1, Duration::from_secs(10));
+        watcher.push(2, Duration::from_secs(20));
+        assert!(watcher.remove(1));
+        assert!(!watcher.remove(1));
+        assert_eq!(drain(&mut watcher), vec![2]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn orders_by_deadline() {
+        let mut watcher = Watcher::new();
+        watcher.push(3, Duration::from_secs(30));
+        watcher.push(1, Duration::from_secs(10));
+        watcher.push(4, Duration::from_secs(40));
+        watcher.push(2, Duration::from_secs(20));
+        assert_eq!(drain(&mut watcher), vec![1, 2, 3, 4]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_for_deadline() {
+        let start = Instant::now();
+        let mut watcher: Watcher<u32> = [(1, Duration::from_secs(5)), (2, Duration::from_secs(3))]
+            .into_iter()
+            .collect();
+        assert_eq!(
+            watcher.next().await,
+            Some((2, start + Duration::from_secs(3)))
+        );
+        assert_eq!(Instant::now(), start + Duration::from_secs(3));
+        assert_eq!(
+            watcher.next().await,
+            Some((1, start + Duration::from_secs(5)))
+        );
+        assert_eq!(watcher.next().await, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn does_not_drift() {
+        let period = Duration::from_secs(60);
+        let start = Instant::now();
+        let mut watcher = Watcher::new();
+        watcher.push(1, period);
+        watcher.push(2, period / 2);
+
+        for _ in 0..1000 {
+            let (id, due) = watcher.next().await.unwrap();
+            // handling a cycle takes a while, which must not delay the next one
+            time::sleep(Duration::from_secs(7)).await;
+            watcher.push_periodic(id, due, period);
+        }
+
+        let (_, due) = watcher.pop().unwrap();
+        assert_eq!(due - start, period * 500 + period / 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn skips_missed_cycles() {
+        let mut watcher = Watcher::new();
+        watcher.push(1, Duration::from_secs(1));
+        let (id, due) = watcher.next().await.unwrap();
+        time::sleep(Duration::from_secs(10)).await;
+        watcher.push_periodic(id, due, Duration::from_secs(1));
+        assert_eq!(watcher.next().await, Some((1, Instant::now())));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn compacts_stale_entries() {
+        let mut watcher = Watcher::new();
+        for i in 0..1000 {
+            watcher.push(1, Duration::from_secs(i));
+        }
+        assert!(watcher.heap.len() <= 18);
+        assert_eq!(drain(&mut watcher), vec![1]);
+    }
+
+    #[test]
+    fn phase_hash_is_fixed() {
+        let mut hasher = Fnv1a::default();
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn restores_persisted_schedule() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let clock = ManualClock::new(now);
+        let start = clock.now();
+        let minute = Duration::from_secs(60);
+        let mut watcher = Watcher::with_clock(clock, Jitter::default());
+        watcher.restore(
+            [
+                (1, Some(now + minute * 10)),
+                (2, Some(now - minute)),
+                (3, None),
+                (4, Some(now - minute * 60)),
+            ],
+            Duration::from_secs(60),
+        );
+
+        let fired: Vec<_> = std::iter::from_fn(|| watcher.pop())
+            .map(|(id, due)| (id, due - start))
+            .collect();
+        let stagger = Duration::from_secs(5);
+        assert_eq!(
+            fired,
+            vec![
+                (3, Duration::ZERO),
+                (4, stagger),
+                (2, stagger * 2),
+                (1, minute * 10)
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn spreads_many_overdue_entries_over_window() {
+        let window = Duration::from_secs(60);
+        let mut watcher = Watcher::new();
+        watcher.restore((0..120).map(|id| (id, None)), window);
+        let start = Instant::now();
+        let mut last = start;
+        while let Some((_, due)) = watcher.next().await {
+            last = due;
+        }
+        assert!(last - start < window);
+        assert_eq!(last - start, Duration::from_millis(500) * 119);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn offsets_phase_per_id() {
+        let period = Duration::from_secs(600);
+        let start = Instant::now();
+        let mut watcher = Watcher::with_jitter(Jitter {
+            phase: 0.5,
+            spread: 0.0,
+        });
+        for id in 0..10u32 {
+            watcher.push_periodic(id, start, period);
+        }
+
+        let mut offsets = Vec::new();
+        for _ in 0..30 {
+            let (id, due) = watcher.next().await.unwrap();
+            let offset = Instant::now() - due;
+            assert!(offset < period / 2);
+            offsets.push((id, offset));
+            watcher.push_periodic(id, due, period);
+        }
+        // every id keeps its own phase from cycle to cycle
+        for &(id, offset) in &offsets {
+            assert!(offsets
+                .iter()
+                .filter(|(other, _)| *other == id)
+                .all(|(_, other)| *other == offset));
+        }
+        let mut distinct: Vec<_> = offsets.iter().map(|(_, offset)| *offset).collect();
+        distinct.sort();
+        distinct.dedup();
+        assert!(distinct.len() > 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn spread_stays_in_bounds_without_drifting() {
+        let period = Duration::from_secs(100);
+        let start = Instant::now();
+        let mut watcher = Watcher::with_jitter(Jitter {
+            phase: 0.0,
+            spread: 0.1,
+        });
+        watcher.push_periodic(1, start, period);
+
+        for cycle in 1..=500u32 {
+            let (_, due) = watcher.next().await.unwrap();
+            assert_eq!(due, start + period * cycle);
+            let now = Instant::now();
+            let deviation = if now > due { now - due } else { due - now };
+            assert!(deviation <= period / 10);
+            watcher.push_periodic(1, due, period);
+        }
+    }
+
+    /// Polls `next` once, the way the monitoring loop would between other events.
+    async fn poll_once<F: Future>(next: &mut Pin<Box<F>>) -> Option<F::Output> {
+        match poll_fn(|cx| Poll::Ready(next.as_mut().poll(cx))).await {
+            Poll::Ready(output) => Some(output),
+            Poll::Pending => None,
+        }
+    }
+
+    fn virtual_watcher() -> (Arc<ManualClock>, Watcher<u32>) {
+        let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000));
+        let watcher = Watcher::with_clock(clock.clone(), Jitter::default());
+        (clock, watcher)
+    }
+
+    #[tokio::test]
+    async fn virtual_clock_fires_on_deadline() {
+        let (clock, mut watcher) = virtual_watcher();
+        let start = clock.now();
+        watcher.push(1, Duration::from_secs(10));
+        watcher.push(2, Duration::from_secs(20));
+
+        let mut next = Box::pin(watcher.next());
+        assert_eq!(poll_once(&mut next).await, None);
+        clock.advance(Duration::from_secs(9));
+        assert_eq!(poll_once(&mut next).await, None);
+        clock.advance(Duration::from_secs(1));
+        let fired = poll_once(&mut next).await.flatten();
+        assert_eq!(fired, Some((1, start + Duration::from_secs(10))));
+        drop(next);
+
+        let (_, due) = fired.unwrap();
+        watcher.push_periodic(1, due, Duration::from_secs(30));
+        let mut next = Box::pin(watcher.next());
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(
+            poll_once(&mut next).await.flatten(),
+            Some((2, start + Duration::from_secs(20)))
+        );
+        drop(next);
+        let mut next = Box::pin(watcher.next());
+        clock.advance(Duration::from_secs(19));
+        assert_eq!(poll_once(&mut next).await, None);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(
+            poll_once(&mut next).await.flatten(),
+            Some((1, start + Duration::from_secs(40)))
+        );
+    }
+
+    #[tokio::test]
+    async fn virtual_clock_follows_reschedule() {
+        let (clock, mut watcher) = virtual_watcher();
+        let start = clock.now();
+        watcher.push(1, Duration::from_secs(10));
+        watcher.push(1, Duration::from_secs(60));
+
+        let mut next = Box::pin(watcher.next());
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(poll_once(&mut next).await, None);
+        clock.advance(Duration::from_secs(50));
+        assert_eq!(
+            poll_once(&mut next).await.flatten(),
+            Some((1, start + Duration::from_secs(60)))
+        );
+    }
+
+    #[tokio::test]
+    async fn virtual_clock_applies_interval_change_from_now() {
+        let (clock, mut watcher) = virtual_watcher();
+        let start = clock.now();
+        let minute = Duration::from_secs(60);
+        watcher.push_periodic(1, start, minute * 10);
+        clock.advance(minute * 5);
+
+        // what the monitoring loop does when the user picks another interval
+        watcher.push_periodic(1, clock.now(), minute);
+        let mut next = Box::pin(watcher.next());
+        assert_eq!(poll_once(&mut next).await, None);
+        clock.advance(minute);
+        assert_eq!(
+            poll_once(&mut next).await.flatten(),
+            Some((1, start + minute * 6))
+        );
+    }
+
+    #[tokio::test]
+    async fn virtual_clock_drops_removed_entries() {
+        let (clock, mut watcher) = virtual_watcher();
+        watcher.push(1, Duration::from_secs(10));
+        let mut next = Box::pin(watcher.next());
+        assert_eq!(poll_once(&mut next).await, None);
+        drop(next);
+
+        assert!(watcher.remove(1));
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(watcher.next().await, None);
     }
 }
@@ -0,0 +1,314 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+/// How far ahead [`Cron::next_after`] looks before giving up. Covers a leap day
+/// falling on any weekday.
+const SEARCH_DAYS: u64 = 8 * 366;
+
+/// When a job comes due again.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Recurrence {
+    /// A fixed time after the previous run.
+    Every(Duration),
+    /// Whenever the wall clock matches.
+    Cron(Cron),
+}
+
+impl Recurrence {
+    /// The first run strictly after `now`, for a job that has never run.
+    pub fn first_after(&self, now: SystemTime) -> Option<SystemTime> {
+        match self {
+            Recurrence::Every(period) => Some(now + *period),
+            Recurrence::Cron(cron) => cron.next_after(now),
+        }
+    }
+}
+
+/// Parses either a cron expression or a duration such as `6h` or `1d12h`.
+impl FromStr for Recurrence {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.split_whitespace().count() == 5 {
+            s.parse().map(Recurrence::Cron)
+        } else {
+            parse_duration(s)
+                .map(Recurrence::Every)
+                .ok_or(Error::Duration)
+        }
+    }
+}
+
+/// Parses a sequence of amounts with units, `s`, `m`, `h` or `d`, like `1h30m`.
+/// Zero durations are rejected.
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let mut total = Duration::ZERO;
+    let mut rest = s.trim();
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let amount: u64 = rest[..digits].parse().ok()?;
+        let unit = match rest[digits..].chars().next()? {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => SECONDS_PER_DAY,
+            _ => return None,
+        };
+        total = total.checked_add(Duration::from_secs(amount.checked_mul(unit)?))?;
+        rest = &rest[digits + 1..];
+    }
+    (!total.is_zero()).then_some(total)
+}
+
+/// Writes the duration the way [`parse_duration`] reads it, largest unit first,
+/// dropping fractions of a second.
+pub fn format_duration(duration: Duration) -> String {
+    let mut rest = duration.as_secs();
+    let mut text = String::new();
+    for (unit, secs) in [('d', SECONDS_PER_DAY), ('h', 60 * 60), ('m', 60), ('s', 1)] {
+        if rest >= secs {
+            text += &format!("{}{}", rest / secs, unit);
+            rest %= secs;
+        }
+    }
+    if text.is_empty() {
+        text += "0s";
+    }
+    text
+}
+
+/// A five field cron expression, `minute hour day-of-month month day-of-week`,
+/// evaluated in UTC. Fields take `*`, numbers, ranges `a-b`, steps `/n` and
+/// comma separated lists of those. As usual, a day matches if either of the day
+/// fields does when both are restricted.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    /// The first matching minute strictly after `after`.
+    pub fn next_after(&self, after: SystemTime) -> Option<SystemTime> {
+        let secs = after.duration_since(UNIX_EPOCH).ok()?.as_secs();
+        let first_minute = secs / 60 + 1;
+        let first_day = first_minute * 60 / SECONDS_PER_DAY;
+        for day in first_day..first_day + SEARCH_DAYS {
+            if !self.matches_day(day) {
+                continue;
+            }
+            let start = if day == first_day {
+                first_minute % (SECONDS_PER_DAY / 60)
+            } else {
+                0
+            };
+            let minute_of_day = (start..SECONDS_PER_DAY / 60)
+                .find(|m| has(self.hours, m / 60) && has(self.minutes, m % 60));
+            if let Some(minute_of_day) = minute_of_day {
+                return Some(
+                    UNIX_EPOCH + Duration::from_secs(day * SECONDS_PER_DAY + minute_of_day * 60),
+                );
+            }
+        }
+        None
+    }
+
+    fn matches_day(&self, days_since_epoch: u64) -> bool {
+        let (_, month, day) = civil_from_days(days_since_epoch);
+        if !has(self.months, month) {
+            return false;
+        }
+        // 1970-01-01 was a Thursday
+        let weekday = (days_since_epoch + 4) % 7;
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (false, true) => has(self.days, day),
+            (true, false) => has(self.weekdays, weekday),
+            (false, false) => has(self.days, day) || has(self.weekdays, weekday),
+        }
+    }
+}
+
+impl FromStr for Cron {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<_> = s.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(Error::FieldCount(fields.len()));
+        };
+        let mut weekdays_set = parse_field(weekdays, 0, 7)?;
+        // both 0 and 7 stand for Sunday
+        if has(weekdays_set, 7) {
+            weekdays_set |= 1;
+        }
+        Ok(Cron {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: weekdays_set,
+            any_day: days == "*",
+            any_weekday: weekdays == "*",
+        })
+    }
+}
+
+/// Parses one field into a bit set of the values it matches.
+fn parse_field(field: &str, min: u64, max: u64) -> Result<u64, Error> {
+    let invalid = || Error::Field(field.to_string());
+    let mut set = 0;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse().map_err(|_| invalid())?),
+            None => (item, 1),
+        };
+        let (from, to) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((from, to)) => (
+                    from.parse().map_err(|_| invalid())?,
+                    to.parse().map_err(|_| invalid())?,
+                ),
+                None => {
+                    let value = range.parse().map_err(|_| invalid())?;
+                    // `5/15` runs from 5 to the end of the range
+                    (value, if item.contains('/') { max } else { value })
+                }
+            },
+        };
+        if step == 0 || from < min || to > max || from > to {
+            return Err(invalid());
+        }
+        for value in (from..=to).step_by(step) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+fn has(set: u64, value: u64) -> bool {
+    set & (1 << value) != 0
+}
+
+/// Year, month and day of a count of days since 1970-01-01.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u64;
+    (year, month, day)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    FieldCount(usize),
+    Field(String),
+    Duration,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::FieldCount(count) => write!(f, "expected 5 cron fields, found {}", count),
+            Error::Field(field) => write!(f, "invalid cron field {}", field),
+            Error::Duration => write!(f, "invalid duration"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-01-01T00:00:00Z, a Monday.
+    fn new_year() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_704_067_200)
+    }
+
+    fn hours(n: u64) -> Duration {
+        Duration::from_secs(n * 60 * 60)
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("45m"), Some(Duration::from_secs(45 * 60)));
+        assert_eq!(parse_duration("1d12h"), Some(hours(36)));
+        assert_eq!(parse_duration("0m"), None);
+        assert_eq!(parse_duration("5"), None);
+        assert_eq!(parse_duration("5w"), None);
+    }
+
+    #[test]
+    fn formats_durations_as_parsed() {
+        for text in ["45m", "2h", "1d12h", "1h30m15s"] {
+            assert_eq!(format_duration(parse_duration(text).unwrap()), text);
+        }
+        assert_eq!(format_duration(Duration::from_millis(500)), "0s");
+    }
+
+    #[test]
+    fn finds_next_daily_run() {
+        let cron: Cron = "30 3 * * *".parse().unwrap();
+        let first = cron.next_after(new_year()).unwrap();
+        assert_eq!(first, new_year() + hours(3) + Duration::from_secs(30 * 60));
+        assert_eq!(cron.next_after(first).unwrap(), first + hours(24));
+    }
+
+    #[test]
+    fn honours_weekdays_and_steps() {
+        let cron: Cron = "0 */6 * * 6,0".parse().unwrap();
+        // the first weekend of 2024 starts on Saturday the 6th
+        let first = cron.next_after(new_year()).unwrap();
+        assert_eq!(first, new_year() + hours(5 * 24));
+        assert_eq!(cron.next_after(first).unwrap(), first + hours(6));
+        let sunday: Cron = "0 0 * * 7".parse().unwrap();
+        assert_eq!(
+            sunday.next_after(new_year()),
+            Some(new_year() + hours(6 * 24))
+        );
+    }
+
+    #[test]
+    fn matches_either_day_field() {
+        // the 15th, or any Monday
+        let cron: Cron = "0 12 15 * 1".parse().unwrap();
+        let first = cron.next_after(new_year()).unwrap();
+        assert_eq!(first, new_year() + hours(12));
+        assert_eq!(cron.next_after(first).unwrap(), first + hours(7 * 24));
+    }
+
+    #[test]
+    fn finds_leap_days() {
+        let cron: Cron = "0 0 29 2 *".parse().unwrap();
+        assert_eq!(
+            cron.next_after(new_year()),
+            Some(new_year() + hours((31 + 28) * 24))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        assert_eq!("* * *".parse::<Cron>(), Err(Error::FieldCount(3)));
+        assert!("60 * * * *".parse::<Cron>().is_err());
+        assert!("*/0 * * * *".parse::<Cron>().is_err());
+        assert!("5-1 * * * *".parse::<Cron>().is_err());
+        assert!(matches!(
+            "0 3 * * *".parse::<Recurrence>(),
+            Ok(Recurrence::Cron(_))
+        ));
+        assert_eq!("6h".parse::<Recurrence>(), Ok(Recurrence::Every(hours(6))));
+    }
+}